@@ -0,0 +1,58 @@
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::{to_json_binary, Addr, BankMsg, Coin, CosmosMsg, Empty, StdResult, Uint128, WasmMsg};
+use cw20::Cw20ExecuteMsg;
+
+use crate::state::ibc::AssetKind;
+use crate::state::Cw20Route;
+
+/// Execute messages of the cw20-adapter (InjectiveLabs/cw20-adapter) this contract uses.
+/// The adapter mints `factory/{adapter}/{cw20}` to whoever sends it the CW20 with a
+/// `Send`, whatever the `msg`, and redeems it back to the CW20.
+#[cw_serde]
+pub enum AdapterExecuteMsg {
+    /// Burns the attached factory denom and transfers as many CW20 tokens to `recipient`
+    RedeemAndTransfer { recipient: Option<String> },
+}
+
+/// Token factory denom the adapter mints for the CW20 `contract`
+pub fn factory_denom(adapter: &Addr, contract: &Addr) -> String {
+    format!("factory/{adapter}/{contract}")
+}
+
+/// Sends `amount` of the route CW20 to its adapter, which mints the same amount
+/// of the factory denom to this contract. The minted amount is checked in the reply.
+pub fn unwrap_msg(cw20: &Cw20Route, amount: Uint128) -> StdResult<CosmosMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: cw20.contract.to_string(),
+        msg: to_json_binary(&Cw20ExecuteMsg::Send {
+            contract: cw20.adapter.to_string(),
+            amount,
+            msg: to_json_binary(&Empty {})?,
+        })?,
+        funds: vec![],
+    }
+    .into())
+}
+
+/// Sends `amount` to `recipient` in the form the packet was funded with. The factory
+/// denom of a CW20 packet is redeemed by the adapter, which transfers the CW20 to
+/// `recipient`; anything else is a bank send.
+pub fn payout_msg(asset: &AssetKind, recipient: &Addr, amount: Coin) -> StdResult<CosmosMsg> {
+    match asset {
+        AssetKind::Cw20 { contract, adapter } if amount.denom == factory_denom(adapter, contract) => {
+            Ok(WasmMsg::Execute {
+                contract_addr: adapter.to_string(),
+                msg: to_json_binary(&AdapterExecuteMsg::RedeemAndTransfer {
+                    recipient: Some(recipient.to_string()),
+                })?,
+                funds: vec![amount],
+            }
+            .into())
+        }
+        _ => Ok(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![amount],
+        }
+        .into()),
+    }
+}
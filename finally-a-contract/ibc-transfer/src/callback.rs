@@ -0,0 +1,58 @@
+use cosmwasm_std::{to_json_binary, Coin, Reply, Response, StdResult, SubMsg, SubMsgResult, WasmMsg};
+
+use crate::consts::{MsgReplyID, CALLBACK_GAS_LIMIT};
+use crate::msg::{CallbackMsg, IbcTransferOutcome, QuoteRefund};
+use crate::state::ibc::{IBCTransfer, TransferCallback};
+use crate::state::Quote;
+use crate::ContractError;
+
+/// Executes the callback contract of `packet`, if it has one, with its terminal status.
+/// Errors of the callback are replied to and swallowed, they don't revert the transfer.
+pub fn outcome_msg(packet: &IBCTransfer, payout: Option<Coin>) -> StdResult<Option<SubMsg>> {
+    let Some(callback) = &packet.callback else {
+        return Ok(None);
+    };
+    let outcome = IbcTransferOutcome {
+        channel: packet.channel_id.clone(),
+        sequence: packet.sequence,
+        sender: packet.recovery_addr.clone(),
+        status: packet.status.clone(),
+        payout,
+        msg: callback.msg.clone(),
+    };
+    execute_msg(callback, CallbackMsg::IbcTransferOutcome(outcome)).map(Some)
+}
+
+/// Executes the callback contract of a quote, if it has one, with its refund
+pub fn quote_refund_msg(quote: &Quote) -> StdResult<Option<SubMsg>> {
+    let Some(callback) = &quote.callback else {
+        return Ok(None);
+    };
+    let refund = QuoteRefund {
+        quote_id: quote.id,
+        sender: quote.sender.clone(),
+        refund: quote.amount.clone(),
+        msg: callback.msg.clone(),
+    };
+    execute_msg(callback, CallbackMsg::IbcTransferQuoteRefund(refund)).map(Some)
+}
+
+fn execute_msg(callback: &TransferCallback, msg: CallbackMsg) -> StdResult<SubMsg> {
+    let execute = WasmMsg::Execute {
+        contract_addr: callback.contract.to_string(),
+        msg: to_json_binary(&msg)?,
+        funds: vec![],
+    };
+    Ok(SubMsg::reply_on_error(execute, MsgReplyID::Callback.repr()).with_gas_limit(CALLBACK_GAS_LIMIT))
+}
+
+/// A callback contract failed, its state changes are reverted and the transfer goes on
+pub fn handle_callback_reply(reply: Reply) -> Result<Response, ContractError> {
+    let error = match reply.result {
+        SubMsgResult::Err(err) => err,
+        SubMsgResult::Ok(_) => String::new(),
+    };
+    Ok(Response::new()
+        .add_attribute("action", "callback_failed")
+        .add_attribute("error", error))
+}
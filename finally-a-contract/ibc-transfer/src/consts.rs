@@ -1,8 +1,32 @@
 use enum_repr::EnumRepr;
 
+// Multiple of the sent amount the external address pays back
+pub const PAYOUT_MULTIPLIER: u128 = 2;
+
+// Default time the external address has to pay back an acked packet (1 day)
+pub const DEFAULT_RESPONSE_DEADLINE: u64 = 24 * 60 * 60;
+
+// Pagination limits for queries
+pub const DEFAULT_QUERY_LIMIT: u32 = 10;
+pub const MAX_QUERY_LIMIT: u32 = 30;
+
+// Pagination limits for sweeping expired packets
+pub const DEFAULT_SWEEP_LIMIT: u32 = 10;
+pub const MAX_SWEEP_LIMIT: u32 = 30;
+
+// Longest memo accepted on outgoing packets, the ibc-go transfer module limit
+pub const MAX_MEMO_LENGTH: usize = 32768;
+
+// Gas an outcome callback may use, so a callback contract can't make lifecycle handlers run out of gas
+pub const CALLBACK_GAS_LIMIT: u64 = 500_000;
+
 // Msg Reply IDs
 #[EnumRepr(type = "u64")]
 pub enum MsgReplyID {
     TransferIbc = 1,
     SendAddr = 2,
+    Callback = 3,
+    Cw20Unwrap = 4,
+    // only sent by the native-ibc entry points
+    VoucherReturn = 5,
 }
\ No newline at end of file
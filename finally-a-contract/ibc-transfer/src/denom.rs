@@ -0,0 +1,44 @@
+use cosmwasm_std::{QuerierWrapper, StdError, StdResult};
+use sha2::{Digest, Sha256};
+
+use crate::execute::ibc_channel_id;
+use crate::proto::QueryDenomTraceRequest;
+
+/// ICS-20 voucher denom of `base_denom` received over (`port`, `channel`) on this chain:
+/// `ibc/` followed by the upper case hex SHA-256 of the trace path `port/channel/base_denom`
+pub fn ibc_denom(port: &str, channel: &str, base_denom: &str) -> String {
+    let hash = Sha256::digest(format!("{port}/{channel}/{base_denom}").as_bytes());
+    format!("ibc/{}", hex::encode_upper(hash))
+}
+
+/// Full trace of `denom`. An `ibc/` voucher is resolved with the DenomTrace query of the
+/// transfer module, a native denom is its own trace.
+pub fn denom_trace(querier: &QuerierWrapper, denom: &str) -> StdResult<String> {
+    let Some(hash) = denom.strip_prefix("ibc/") else {
+        return Ok(denom.to_string());
+    };
+    let trace = QueryDenomTraceRequest { hash: hash.to_string() }
+        .query(querier)?
+        .denom_trace
+        .ok_or_else(|| StdError::not_found(format!("denom trace of {denom}")))?;
+    Ok(format!("{}/{}", trace.path, trace.base_denom))
+}
+
+/// Local denom of the full trace `trace`, the inverse of denom_trace. A trace whose path
+/// starts with a `port/channel-<n>` hop is an `ibc/` voucher, anything else is native.
+#[cfg(feature = "native-ibc")]
+pub fn trace_denom(trace: &str) -> String {
+    let mut segments = trace.split('/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some(_), Some(channel), Some(_)) if channel.starts_with("channel-") => {
+            format!("ibc/{}", hex::encode_upper(Sha256::digest(trace.as_bytes())))
+        }
+        _ => trace.to_string(),
+    }
+}
+
+/// Voucher `denom` comes back as when the counterparty returns it over (`port`, `channel`),
+/// with `channel` the channel number of CHANNELS
+pub fn returning_voucher(querier: &QuerierWrapper, port: &str, channel: u32, denom: &str) -> StdResult<String> {
+    Ok(ibc_denom(port, &ibc_channel_id(&channel.to_string()), &denom_trace(querier, denom)?))
+}
@@ -1,4 +1,7 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{
+    CheckedFromRatioError, CheckedMultiplyFractionError, Decimal, DecimalRangeExceeded, OverflowError, StdError,
+    Timestamp, Uint128,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -23,6 +26,18 @@ pub enum ContractError {
     #[error("Not enough funds sent by user")]
     NotEnoughFunds { sent: u128, required: u128 },
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("{0}")]
+    CheckedMultiplyFraction(#[from] CheckedMultiplyFractionError),
+
+    #[error("{0}")]
+    CheckedFromRatio(#[from] CheckedFromRatioError),
+
+    #[error("{0}")]
+    DecimalRangeExceeded(#[from] DecimalRangeExceeded),
+
     #[error("{0}")]
     JsonSerialization(#[from] serde_json_wasm::ser::Error),
 
@@ -38,6 +53,9 @@ pub enum ContractError {
     #[error("Contract locked")]
     ContractLocked { msg: String },
 
+    #[error("Packet {channel_id} {sequence} has no anomaly to resolve")]
+    NoAnomaly { channel_id: String, sequence: u64 },
+
     #[error("No inflight packet")]
     NoInflightPacket { channel_id: String, sequence: u64 },
 
@@ -46,4 +64,82 @@ pub enum ContractError {
 
     #[error("Invalid flight packet state")]
     InvalidInflightPacketState { channel_id: String, sequence: u64, status: String},
-}
\ No newline at end of file
+
+    #[error("No funds sent for the insurance pool")]
+    EmptyInsuranceDeposit,
+
+    #[error("Not enough funds in the insurance pool")]
+    InsufficientPoolFunds { denom: String, available: u128, requested: u128 },
+
+    #[error("No surplus to sweep")]
+    NoSurplus { denom: String },
+
+    #[error("Memo is {length} bytes, the limit is {max}")]
+    MemoTooLong { length: usize, max: usize },
+
+    #[error("Either a correlation id or a channel and sequence are required")]
+    MissingPacketKey {},
+
+    #[error("Unknown correlation id {correlation_id}")]
+    UnknownCorrelationId { correlation_id: u64 },
+
+    #[error("CW20 {contract} is not accepted on this route")]
+    UnsupportedCw20 { contract: String },
+
+    #[error("Adapter minted {minted} {denom} for {amount} CW20 tokens")]
+    Cw20NotMinted { denom: String, minted: Uint128, amount: Uint128 },
+
+    #[error("A payout must carry exactly one coin")]
+    InvalidPayoutFunds {},
+
+    #[error("No packet waiting for a payout from {payer} in {denom}")]
+    NoPayoutMatch { payer: String, denom: String },
+
+    #[error("Payout rate of {denom} must be positive")]
+    ZeroPayoutRate { denom: String },
+
+    #[error("No price feed configured")]
+    NoPriceFeed {},
+
+    #[error("Price of {denom} was last updated at {updated_at} and is stale")]
+    StalePrice { denom: String, updated_at: Timestamp },
+
+    #[error("Send is worth {value} USD, the route limit is {limit}")]
+    UsdLimitExceeded { value: Decimal, limit: Decimal },
+
+    #[error("Channels must be unordered")]
+    InvalidChannelOrder {},
+
+    #[error("Channel version {version} is not ics20-1")]
+    InvalidChannelVersion { version: String },
+
+    #[error("Channel {channel_id} was closed")]
+    ChannelClosed { channel_id: String },
+
+    #[error("Channel {channel_id} of the contract port is open, the chain reports its close")]
+    NativeChannelOpen { channel_id: String },
+
+    #[error("No open channel {channel_id} on the contract port")]
+    UnknownNativeChannel { channel_id: String },
+
+    #[error("{denom} is not a voucher of a token this contract sent")]
+    NotReturningVoucher { denom: String },
+
+    #[error("Sender {sender} is denylisted")]
+    SenderDenied { sender: String },
+
+    #[error("Sender {sender} is not allowlisted")]
+    SenderNotAllowlisted { sender: String },
+
+    #[error("Quote ttl must be positive")]
+    ZeroQuoteTtl {},
+
+    #[error("Quote {quote_id} expired")]
+    QuoteExpired { quote_id: u64 },
+
+    #[error("Route {route} would owe {exposure} {denom}, its exposure cap is {cap}")]
+    ExposureCapExceeded { route: String, denom: String, exposure: Uint128, cap: Uint128 },
+
+    #[error("Route {route} caps its exposure in {cap_denom}, it can't owe {denom}")]
+    ExposureDenomMismatch { route: String, denom: String, cap_denom: String },
+}
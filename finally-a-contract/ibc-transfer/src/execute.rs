@@ -1,22 +1,36 @@
-use std::ops::Mul;
-
 use cosmwasm_std::{
-    coins, Addr, BankMsg, Coin, DepsMut, Env, MessageInfo, Reply, Response, SubMsg, SubMsgResponse,
-    SubMsgResult, Deps, to_binary,
+    coin, coins, from_json, to_json_binary, Addr, BankMsg, Coin, Deps, DepsMut, Empty, Env, MessageInfo, Order,
+    Reply, Response, Storage, SubMsg, SubMsgResponse, SubMsgResult, Uint128,
 };
+use cw20::Cw20ReceiveMsg;
+use cw_storage_plus::{Bound, Map};
 use schemars::_serde_json::json;
 use should_load::assignment::MapShouldLoad;
 
-use crate::consts::MsgReplyID;
-use crate::ibc_lifecycle::create_recovery;
+use crate::asset::{factory_denom, payout_msg, unwrap_msg};
+use crate::callback;
+use crate::consts::{MsgReplyID, DEFAULT_SWEEP_LIMIT, MAX_SWEEP_LIMIT};
+use crate::ibc_lifecycle::{self, create_recovery, fail_over_channel, pay_recovery};
+use crate::denom::returning_voucher;
+use crate::ledger;
+use crate::oracle::{self, FreshPrices, PriceFeed};
+use crate::msg::Cw20HookMsg;
+use crate::msg::SettledPacket;
+use crate::payout_queue;
+use crate::quote;
+use crate::stats;
+use crate::memo::{MemoBuilder, ReturnHook, WasmHook};
 use crate::proto::MsgTransferResponse;
-use crate::state::ibc::IBCTransfer;
+use crate::state::ibc::{BlockStamp, CompletedTransfer, IBCTransfer};
 use crate::state::{
-    ibc, TransferMsgReplyState, CHANNELS, INFLIGHT_PACKETS, PORTS, RECOVERY_STATES,
-    SEND_EXTERNAL_TOKENS_REPLY_STATE, TRANSFER_REPLY_STATE, CONFIG,
+    ibc, UnwrapReplyState, ACCESS_CONTROL, CLOSED_CHANNELS, CW20_UNWRAP_REPLY_STATE, SENDER_ALLOWLIST, SENDER_DENYLIST, PayoutReplyState, PriceFeedConfig, RateSource, ReturnDenom, RouteConfig, TransferMsgReplyState, CHANNELS, COMPLETED_TRANSFERS, CONFIG,
+    CORRELATION_IDS, NEXT_CORRELATION_ID,
+    INFLIGHT_PACKETS, INSURANCE_POOLS, INSURED_PACKETS, PORTS, RECOVERY_STATES, RESPONSE_DEADLINES, ROUTES,
+    SEND_EXTERNAL_TOKENS_REPLY_STATE, TRANSFER_REPLY_STATE,
 };
 use crate::{proto, state::EXTERNAL_ADDRESSES, ContractError};
 
+#[allow(clippy::too_many_arguments)]
 pub fn transfer_ibc_token(
     deps: DepsMut,
     env: Env,
@@ -25,7 +39,10 @@ pub fn transfer_ibc_token(
     port: String,
     channel: String,
     recipient: String,
+    callback: Option<ibc::TransferCallback>,
 ) -> Result<Response, ContractError> {
+    check_sender(deps.as_ref(), &info.sender)?;
+    validate_callback(deps.as_ref(), &callback)?;
     // make sure some token is sent to this contract
     let sent_funds = info
         .funds
@@ -43,40 +60,247 @@ pub fn transfer_ibc_token(
         });
     }
 
-    // get the recipient from state
-    let recipient = EXTERNAL_ADDRESSES
-        .should_load(deps.storage, recipient)
-        .unwrap_or_else(|_| {
-            EXTERNAL_ADDRESSES
-                .should_load(deps.storage, "default".to_string())
-                .unwrap()
+    send_or_quote(deps, env, info.sender, amount, ibc::AssetKind::Native, port, channel, recipient, callback)
+}
+
+fn validate_callback(deps: Deps, callback: &Option<ibc::TransferCallback>) -> Result<(), ContractError> {
+    if let Some(callback) = callback {
+        deps.api.addr_validate(callback.contract.as_str())?;
+    }
+    Ok(())
+}
+
+/// Rejects denylisted senders, and senders missing from the allowlist while it is enabled
+fn check_sender(deps: Deps, sender: &Addr) -> Result<(), ContractError> {
+    if SENDER_DENYLIST.has(deps.storage, sender) {
+        return Err(ContractError::SenderDenied {
+            sender: sender.to_string(),
         });
-    let port = PORTS.should_load(deps.storage, port).unwrap_or_else(|_| {
-        PORTS
-            .should_load(deps.storage, "default".to_string())
-            .unwrap()
-    });
-    let channel = CHANNELS
-        .should_load(deps.storage, channel)
-        .unwrap_or_else(|_| {
+    }
+    let access = ACCESS_CONTROL.may_load(deps.storage)?.unwrap_or_default();
+    if access.allowlist_enabled && !SENDER_ALLOWLIST.has(deps.storage, sender) {
+        return Err(ContractError::SenderNotAllowlisted {
+            sender: sender.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// SendToken for CW20 tokens sent to the contract with a Receive hook. The CW20 must be
+/// the one of the route; it is unwrapped to its factory denom before the transfer.
+///
+/// The cw20-ics20 flow isn't supported: that contract sends the packet itself, so
+/// this contract never learns its sequence nor receives its ack or timeout.
+pub fn receive_cw20(deps: DepsMut, env: Env, info: MessageInfo, msg: Cw20ReceiveMsg) -> Result<Response, ContractError> {
+    let Cw20HookMsg::SendToken { port, channel, recipient, callback } = from_json(&msg.msg)?;
+    let (recipient_alias, _) = recipient_address(deps.storage, recipient.clone());
+    let cw20 = ROUTES
+        .may_load(deps.storage, recipient_alias)?
+        .and_then(|route| route.cw20)
+        .filter(|cw20| cw20.contract == info.sender)
+        .ok_or_else(|| ContractError::UnsupportedCw20 {
+            contract: info.sender.to_string(),
+        })?;
+    let sender = deps.api.addr_validate(&msg.sender)?;
+    check_sender(deps.as_ref(), &sender)?;
+    validate_callback(deps.as_ref(), &callback)?;
+    let amount = coin(msg.amount.u128(), factory_denom(&cw20.adapter, &cw20.contract));
+    let unwrap = unwrap_msg(&cw20, msg.amount)?;
+    let balance = deps.querier.query_balance(&env.contract.address, &amount.denom)?;
+    CW20_UNWRAP_REPLY_STATE.save(deps.storage, &UnwrapReplyState {
+        denom: amount.denom.clone(),
+        balance: balance.amount,
+        amount: msg.amount,
+    })?;
+    let asset = ibc::AssetKind::Cw20 {
+        contract: cw20.contract,
+        adapter: cw20.adapter,
+    };
+
+    let mut res = send_or_quote(deps, env, sender, amount, asset, port, channel, recipient, callback)?;
+    // the factory denom has to be minted before the transfer (or quote) holds it
+    res.messages.insert(0, SubMsg::reply_on_success(unwrap, MsgReplyID::Cw20Unwrap.repr()));
+    Ok(res)
+}
+
+// The adapter received the CW20 of a send. Its reply runs before the transfer, which
+// is reverted with it unless the adapter minted the whole amount to this contract.
+pub fn handle_cw20_unwrap_reply(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let UnwrapReplyState { denom, balance, amount } = CW20_UNWRAP_REPLY_STATE.load(deps.storage)?;
+    CW20_UNWRAP_REPLY_STATE.remove(deps.storage);
+    let minted = deps.querier.query_balance(&env.contract.address, &denom)?.amount.saturating_sub(balance);
+    if minted < amount {
+        return Err(ContractError::Cw20NotMinted { denom, minted, amount });
+    }
+    Ok(Response::new().add_attribute("cw20_unwrapped", denom))
+}
+
+/// (alias, address) of the external address of `alias`, falling back to the default
+pub fn recipient_address(storage: &mut dyn Storage, alias: String) -> (String, String) {
+    match EXTERNAL_ADDRESSES.should_load(storage, alias.clone()) {
+        Ok(address) => (alias, address),
+        Err(_) => (
+            "default".to_string(),
+            EXTERNAL_ADDRESSES
+                .should_load(storage, "default".to_string())
+                .unwrap(),
+        ),
+    }
+}
+
+/// (alias, channel number) of the channel of `alias`, falling back to the default
+pub fn channel_number(storage: &mut dyn Storage, alias: String) -> (String, u32) {
+    match CHANNELS.should_load(storage, alias.clone()) {
+        Ok(channel_id) => (alias, channel_id),
+        Err(_) => (
+            "default".to_string(),
             CHANNELS
+                .should_load(storage, "default".to_string())
+                .unwrap(),
+        ),
+    }
+}
+
+/// Channel id the chain knows a packet channel by. Packets sent through the transfer
+/// module are stored with the channel number of CHANNELS, native ones with the full id.
+pub fn ibc_channel_id(channel: &str) -> String {
+    if channel.starts_with("channel-") {
+        channel.to_string()
+    } else {
+        format!("channel-{channel}")
+    }
+}
+
+/// Sends the transfer, or holds the funds in a quote on routes where the
+/// counterparty accepts sends first
+#[allow(clippy::too_many_arguments)]
+fn send_or_quote(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    amount: Coin,
+    asset: ibc::AssetKind,
+    port: String,
+    channel: String,
+    recipient: String,
+    callback: Option<ibc::TransferCallback>,
+) -> Result<Response, ContractError> {
+    let (recipient_alias, _) = recipient_address(deps.storage, recipient.clone());
+    match ROUTES.may_load(deps.storage, recipient_alias)?.and_then(|route| route.quote) {
+        Some(config) => quote::create(deps, &env, &config, sender, amount, asset, port, channel, recipient, callback),
+        None => send_transfer(deps, env, sender, amount, asset, port, channel, recipient, callback),
+    }
+}
+
+/// Sends `amount`, held by the contract, over IBC on behalf of `sender`
+#[allow(clippy::too_many_arguments)]
+pub fn send_transfer(
+    deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    amount: Coin,
+    asset: ibc::AssetKind,
+    port: String,
+    channel: String,
+    recipient: String,
+    callback: Option<ibc::TransferCallback>,
+) -> Result<Response, ContractError> {
+    // get the recipient, port and channel from state, falling back to the defaults.
+    // The aliases actually used are recorded with the packet.
+    let (recipient_alias, recipient) = recipient_address(deps.storage, recipient);
+    let (port_alias, port) = match PORTS.should_load(deps.storage, port.clone()) {
+        Ok(port_id) => (port, port_id),
+        Err(_) => (
+            "default".to_string(),
+            PORTS
                 .should_load(deps.storage, "default".to_string())
-                .unwrap()
+                .unwrap(),
+        ),
+    };
+    let (channel_alias, channel) = channel_number(deps.storage, channel);
+
+    if CLOSED_CHANNELS.has(deps.storage, &channel.to_string()) {
+        return Err(ContractError::ChannelClosed {
+            channel_id: channel.to_string(),
         });
+    }
+
+    let route = ROUTES.may_load(deps.storage, recipient_alias.clone())?.unwrap_or_default();
+    if let Some(limit) = route.max_send_usd {
+        let config = oracle::price_feed(deps.as_ref())?;
+        let feed = PriceFeed { contract: config.contract };
+        FreshPrices { oracle: &feed, max_age: config.max_age }.check_limit(deps.as_ref(), &env, &amount, limit)?;
+    }
+    let (return_denom, payout_rate) = match &route.payout {
+        Some(payout) => (
+            payout.denom.clone(),
+            Some(oracle::rate(deps.as_ref(), &env, &payout.rate, &amount.denom, &payout.denom)?),
+        ),
+        None => match route.return_denom {
+            ReturnDenom::Native => (amount.denom.clone(), None),
+            ReturnDenom::Voucher => (returning_voucher(&deps.querier, &port, channel, &amount.denom)?, None),
+        },
+    };
+    if let Some(cap) = &route.max_exposure {
+        if cap.denom != return_denom {
+            return Err(ContractError::ExposureDenomMismatch {
+                route: recipient_alias,
+                denom: return_denom,
+                cap_denom: cap.denom.clone(),
+            });
+        }
+        let obligation = ibc::payout_amount(amount.amount.u128(), payout_rate)?;
+        let exposure = stats::exposure(deps.storage, &recipient_alias, &return_denom, Uint128::new(obligation))?;
+        if exposure > cap.amount {
+            return Err(ContractError::ExposureCapExceeded {
+                route: recipient_alias,
+                denom: return_denom,
+                exposure,
+                cap: cap.amount,
+            });
+        }
+    }
+    let correlation_id = if route.return_hook {
+        let id = NEXT_CORRELATION_ID.may_load(deps.storage)?.unwrap_or_default() + 1;
+        NEXT_CORRELATION_ID.save(deps.storage, &id)?;
+        Some(id)
+    } else {
+        None
+    };
+    let state = TransferMsgReplyState {
+        channel_id: channel.to_string(),
+        to_address: recipient,
+        amount: amount.amount.u128(),
+        denom: amount.denom.clone(),
+        sender,
+        return_denom,
+        asset,
+        route: ibc::RouteAliases {
+            recipient: recipient_alias,
+            port: port_alias,
+            channel: channel_alias,
+        },
+        memo: route_memo(&env, route, correlation_id)?,
+        correlation_id,
+        payout_rate,
+        callback,
+    };
+    // Channels of the contract's own port are driven by the native IBC entry points
+    #[cfg(feature = "native-ibc")]
+    if port == crate::native_ibc::contract_port(&env) {
+        return crate::native_ibc::send_packet(deps, env, state);
+    }
 
-    let memo_msg = serde_json_wasm::to_string(&json!({
-        "ibc_callback": env.contract.address.to_string()
-    }))?;
     // build the transfer message
     let transfer_msg = proto::MsgTransfer {
         source_port: port,
-        source_channel: channel.to_string(),
-        token: Some(amount.clone().into()),
+        source_channel: state.channel_id.clone(),
+        token: Some(amount.into()),
         sender: env.contract.address.to_string(),
-        receiver: recipient,
+        receiver: state.to_address.clone(),
         timeout_height: None,
         timeout_timestamp: None,
-        memo: memo_msg,
+        memo: state.memo.clone(),
     };
 
     // Check that there isn't anything stored in TRANSFER_REPLY_STATES. If there
@@ -91,24 +315,34 @@ pub fn transfer_ibc_token(
 
     // Store the ibc send information
     // so that it can be handled by the response
-    TRANSFER_REPLY_STATE.save(
-        deps.storage,
-        &TransferMsgReplyState {
-            channel_id: transfer_msg.source_channel.clone(),
-            to_address: transfer_msg.receiver.clone(),
-            amount: amount.clone().amount.into(),
-            denom: amount.clone().denom,
-            sender: info.sender,
-        },
-    )?;
+    TRANSFER_REPLY_STATE.save(deps.storage, &state)?;
 
-    return Ok(Response::new()
-        .set_data(to_binary(&transfer_msg).unwrap())
+    Ok(Response::new()
+        .set_data(to_json_binary(&transfer_msg)?)
         .add_attribute("ibc_message", format!("{:?}", transfer_msg))
         .add_submessage(SubMsg::reply_on_success(
             transfer_msg,
             MsgReplyID::TransferIbc.repr(),
-        )));
+        )))
+}
+
+/// Memo of a packet sent on `route`. With a correlation id, the memo carries a
+/// return hook calling ReceiveToken with that id.
+fn route_memo(env: &Env, route: RouteConfig, correlation_id: Option<u64>) -> Result<String, ContractError> {
+    let contract = env.contract.address.to_string();
+    let mut memo = MemoBuilder::new()
+        .forward(route.forward_path)
+        .ibc_callback(contract.clone());
+    if let Some(correlation_id) = correlation_id {
+        memo = memo.return_hook(ReturnHook {
+            correlation_id,
+            wasm: WasmHook {
+                contract,
+                msg: json!({ "receive_token": { "correlation_id": correlation_id } }),
+            },
+        });
+    }
+    memo.build()
 }
 
 // Included here so it's closer to the trait that needs it.
@@ -119,6 +353,7 @@ use ::prost::Message; // Proveides ::decode() for MsgTransferResponse
 // If recovery is set to "do_nothing", we just return a response.
 pub fn handle_transfer_ibc_token_reply(
     deps: DepsMut,
+    env: Env,
     msg: cosmwasm_std::Reply,
 ) -> Result<Response, ContractError> {
     // Parse the result from the underlying chain call (IBC send)
@@ -135,28 +370,70 @@ pub fn handle_transfer_ibc_token_reply(
         })?;
 
     // Get the stored context state
+    let state = TRANSFER_REPLY_STATE.load(deps.storage)?;
+    TRANSFER_REPLY_STATE.remove(deps.storage);
+    track_sent_packet(deps, &env, state, response.sequence)
+}
+
+/// Stores a packet that was just sent as in-flight so that its ack or timeout can be handled
+pub fn track_sent_packet(
+    deps: DepsMut,
+    env: &Env,
+    state: TransferMsgReplyState,
+    sequence: u64,
+) -> Result<Response, ContractError> {
     let TransferMsgReplyState {
         channel_id,
         to_address,
         amount,
         denom,
         sender: recovery_addr,
-    } = TRANSFER_REPLY_STATE.load(deps.storage)?;
-    TRANSFER_REPLY_STATE.remove(deps.storage);
+        return_denom,
+        asset,
+        route,
+        memo,
+        correlation_id,
+        payout_rate,
+        callback,
+    } = state;
 
     // Store sent IBC transfer so that it
     // can later be recovered by the sender
+    let packet_hash = Some(ibc_lifecycle::packet_hash(&denom, amount, &to_address, &memo));
     let recovery = ibc::IBCTransfer {
         recovery_addr,
         channel_id: channel_id.clone(),
-        sequence: response.sequence,
+        sequence,
         amount,
         denom: denom.clone(),
+        return_denom,
+        asset,
         status: ibc::PacketLifecycleStatus::Sent,
+        response_deadline: None,
+        route,
+        receiver: to_address.clone(),
+        memo,
+        correlation_id,
+        payout_rate,
+        packet_hash,
+        cancel_requested: false,
+        channel_closed: false,
+        anomaly: None,
+        callback,
+        timeline: ibc::Timeline {
+            sent: Some(BlockStamp::now(env)),
+            ..Default::default()
+        },
     };
 
     // Save as in-flight to be able to manipulate when the ack/timeout is received
-    INFLIGHT_PACKETS.save(deps.storage, (&channel_id, response.sequence), &recovery)?;
+    INFLIGHT_PACKETS.save(deps.storage, (&channel_id, sequence), &recovery)?;
+    stats::sent(deps.storage, &recovery)?;
+    // The id stays resolvable after the packet settles so late payments of insured packets still work
+    if let Some(correlation_id) = correlation_id {
+        CORRELATION_IDS.save(deps.storage, correlation_id, &(channel_id.clone(), sequence))?;
+    }
+    ledger::add_in_flight(deps.storage, &denom, amount)?;
 
     Ok(Response::new()
         .add_attribute("status", "ibc_message_created")
@@ -166,39 +443,78 @@ pub fn handle_transfer_ibc_token_reply(
         .add_attribute("receiver", to_address))
 }
 
+// The bank send of the external tokens to the user has completed. The outcome
+// is decided by the sub-message result alone. Data it returns is decoded as a
+// MsgSendResponse, a decode failure is reported in the `send_response_error`
+// attribute rather than reverting a send that succeeded.
 pub fn handle_send_external_tokens_reply(
     deps: DepsMut,
+    env: Env,
     msg: Reply,
 ) -> Result<Response, ContractError> {
-    // Parse the result from bank sub-messages
-    if let SubMsgResult::Ok(SubMsgResponse { data: Some(_), .. }) = msg.result {
-        // bank transfer was successful so we remove the inflight packet
-        let eti_token = SEND_EXTERNAL_TOKENS_REPLY_STATE
-            .load(deps.storage)
-            .map_err(|_| ContractError::NoExternalTokensInflightPacket)?;
-        INFLIGHT_PACKETS.remove(deps.storage, (&eti_token.channel_id, eti_token.sequence));
-        Ok(Response::new()
-            .add_attribute("msg", "value sent")
-            .add_attribute("recepient", eti_token.recovery_addr)
-            .add_attribute("amount", eti_token.amount.to_string()))
-    } else {
-        // Get the stored context state
-        let eti_token = SEND_EXTERNAL_TOKENS_REPLY_STATE
-            .load(deps.storage)
-            .map_err(|_| ContractError::NoExternalTokensInflightPacket)?;
-
-        // remove the inflight packet
-        INFLIGHT_PACKETS.remove(deps.storage, (&eti_token.channel_id, eti_token.sequence));
-
-        // create a recovery for the original sender of the packet.
-        let recovery_addr = create_recovery(
-            deps,
-            eti_token,
-            ibc::PacketLifecycleStatus::SendingExternalTokensFailure,
-        )?;
-        Ok(Response::new()
-            .add_attribute("msg", "recovery stored")
-            .add_attribute("recovery_addr", recovery_addr))
+    // Get the stored context state and clear it so the next payout can run
+    let PayoutReplyState { mut packet, payout } = SEND_EXTERNAL_TOKENS_REPLY_STATE
+        .load(deps.storage)
+        .map_err(|_| ContractError::NoExternalTokensInflightPacket)?;
+    SEND_EXTERNAL_TOKENS_REPLY_STATE.remove(deps.storage);
+
+    // remove the inflight packet
+    INFLIGHT_PACKETS.remove(deps.storage, (&packet.channel_id, packet.sequence))?;
+
+    match msg.result {
+        SubMsgResult::Ok(SubMsgResponse { data, .. }) => {
+            let decoded = data.map(|b| proto::MsgSendResponse::decode(&b[..])).transpose();
+            // bank transfer was successful, the packet is moved to the archive
+            packet.transition(ibc::PacketLifecycleStatus::PaidOut)?;
+            packet.timeline.paid = Some(BlockStamp::now(&env));
+            stats::paid_out(deps.storage, &packet)?;
+            let recepient = packet.recovery_addr.clone();
+            let outcome = callback::outcome_msg(&packet, Some(payout.clone()))?;
+            ibc_lifecycle::archive(deps.storage, packet, payout.clone())?;
+            let res = Response::new()
+                .add_submessages(outcome)
+                .add_attribute("msg", "value sent")
+                .add_attribute("recepient", recepient)
+                .add_attribute("amount", payout.amount.to_string());
+            match decoded {
+                Ok(_) => Ok(res),
+                Err(err) => Ok(res.add_attribute("send_response_error", err.to_string())),
+            }
+        }
+        SubMsgResult::Err(err) => {
+            // create a recovery of the payout for the original sender of the packet.
+            let (recovery_addr, outcome) = create_recovery(
+                deps,
+                &env,
+                IBCTransfer {
+                    amount: payout.amount.u128(),
+                    denom: payout.denom,
+                    ..packet
+                },
+                ibc::PacketLifecycleStatus::SendingExternalTokensFailure,
+            )?;
+            Ok(Response::new()
+                .add_submessages(outcome)
+                .add_attribute("msg", "recovery stored")
+                .add_attribute("recovery_addr", recovery_addr)
+                .add_attribute("error", err))
+        }
+    }
+}
+
+/// (channel, sequence) of the packet a ReceiveToken refers to. The correlation id wins when given.
+pub fn packet_key(
+    deps: Deps,
+    channel: Option<String>,
+    sequence: Option<u64>,
+    correlation_id: Option<u64>,
+) -> Result<(String, u64), ContractError> {
+    match (correlation_id, channel, sequence) {
+        (Some(correlation_id), _, _) => CORRELATION_IDS
+            .may_load(deps.storage, correlation_id)?
+            .ok_or(ContractError::UnknownCorrelationId { correlation_id }),
+        (None, Some(channel), Some(sequence)) => Ok((channel, sequence)),
+        _ => Err(ContractError::MissingPacketKey {}),
     }
 }
 
@@ -211,78 +527,223 @@ pub fn receive_ibc_token(
     sequence: u64,
 ) -> Result<Response, ContractError> {
     // Get the stored context state
-    let recovery = INFLIGHT_PACKETS
-        .load(deps.storage, (&channel, sequence))
-        .map_err(|_| ContractError::NoInflightPacket {
-            channel_id: channel.clone(),
+    let Some(recovery) = INFLIGHT_PACKETS.may_load(deps.storage, (&channel, sequence))? else {
+        // An expired packet paid out by the insurance pool can still be paid back late
+        if let Some(insured) = INSURED_PACKETS.may_load(deps.storage, (&channel, sequence))? {
+            return reimburse_insurance_pool(deps, info, insured);
+        }
+        // The user of an expired packet nobody insured is still paid when the payment comes late
+        let archived = COMPLETED_TRANSFERS.may_load(deps.storage, (&channel, sequence))?;
+        if let Some(CompletedTransfer { transfer, .. }) = archived {
+            if transfer.status == ibc::PacketLifecycleStatus::ResponseExpired {
+                return pay_expired(deps, info, transfer);
+            }
+        }
+        return Err(ContractError::NoInflightPacket {
+            channel_id: channel,
             sequence,
-        })?;
+        });
+    };
 
     // Check that the packet is in the correct state
+    // A payment after the response deadline is still honoured until the packet is swept
     if recovery.status == ibc::PacketLifecycleStatus::AwaitingResponse {
-        // Get the sent funds from the info
-        let sent_funds = info
-            .funds
-            .iter()
-            .find(|c| c.denom == recovery.denom)
-            .ok_or_else(|| ContractError::NoExternalFunds {
-                denom: recovery.denom.clone(),
-            })?;
         // make sure funds can cover transfer
-        if sent_funds.amount.u128().ge(&recovery.amount.mul(2)) {
-            // Send the funds to the recipient
-            send_external_tokens(deps, recovery)
-        } else {
-            return Err(ContractError::InsufficientExternalFunds {
-                sent: sent_funds.amount.u128(),
-                required: recovery.amount.mul(2),
-            });
-        }
+        check_external_funds(&info, &recovery.return_denom, recovery.payout_amount()?)?;
+        // Send the funds to the recipient
+        send_external_tokens(deps, recovery)
     } else {
-        return Err(ContractError::InvalidInflightPacketState {
+        Err(ContractError::InvalidInflightPacketState {
             channel_id: channel.clone(),
             sequence,
             status: recovery.status.to_string(),
+        })
+    }
+}
+
+/// Payout of the external address arriving through ibc-hooks. The packet is the one
+/// of the correlation id if given, otherwise the oldest acked packet the sender
+/// still has to pay back in the sent denom. The settled packet is returned as data.
+pub fn receive_hook_payout(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    correlation_id: Option<u64>,
+) -> Result<Response, ContractError> {
+    let (channel, sequence) = match correlation_id {
+        Some(correlation_id) => packet_key(deps.as_ref(), None, None, Some(correlation_id))?,
+        None => {
+            let [coin] = info.funds.as_slice() else {
+                return Err(ContractError::InvalidPayoutFunds {});
+            };
+            let denom = coin.denom.clone();
+            let packet = payout_queue::pop(deps.storage, &info.sender, &denom)?.ok_or_else(|| {
+                ContractError::NoPayoutMatch {
+                    payer: info.sender.to_string(),
+                    denom,
+                }
+            })?;
+            (packet.channel_id, packet.sequence)
+        }
+    };
+
+    let res = receive_ibc_token(deps, env, info, channel.clone(), sequence)?;
+    Ok(res
+        .set_data(to_json_binary(&SettledPacket {
+            channel: channel.clone(),
+            sequence,
+        })?)
+        .add_attribute("channel", channel)
+        .add_attribute("sequence", sequence.to_string()))
+}
+
+/// Checks that the external address sent at least `required` of `denom`
+fn check_external_funds(info: &MessageInfo, denom: &str, required: u128) -> Result<(), ContractError> {
+    // Get the sent funds from the info
+    let sent_funds = info
+        .funds
+        .iter()
+        .find(|c| c.denom == denom)
+        .ok_or_else(|| ContractError::NoExternalFunds {
+            denom: denom.to_string(),
+        })?;
+    if sent_funds.amount.u128() < required {
+        return Err(ContractError::InsufficientExternalFunds {
+            sent: sent_funds.amount.u128(),
+            required,
         });
     }
+    Ok(())
+}
+
+/// Credits a late payment for an insured packet back to the insurance pool
+fn reimburse_insurance_pool(deps: DepsMut, info: MessageInfo, packet: IBCTransfer) -> Result<Response, ContractError> {
+    let payout = packet.payout_amount()?;
+    check_external_funds(&info, &packet.return_denom, payout)?;
+
+    INSURED_PACKETS.remove(deps.storage, (&packet.channel_id, packet.sequence));
+    let balance = INSURANCE_POOLS.may_load(deps.storage, &packet.return_denom)?.unwrap_or_default();
+    INSURANCE_POOLS.save(deps.storage, &packet.return_denom, &ledger::checked_add(balance, payout)?)?;
+
+    Ok(Response::new()
+        .add_attribute("msg", "insurance pool reimbursed")
+        .add_attribute("channel", packet.channel_id)
+        .add_attribute("sequence", packet.sequence.to_string())
+        .add_attribute("amount", payout.to_string()))
+}
+
+/// Late payment of an expired packet that was swept without insurance. The packet already
+/// left INFLIGHT_PACKETS and the stats when it was swept, it is taken out of the archive
+/// and paid out like any other.
+fn pay_expired(deps: DepsMut, info: MessageInfo, mut packet: IBCTransfer) -> Result<Response, ContractError> {
+    check_payout_unlocked(deps.storage)?;
+    check_external_funds(&info, &packet.return_denom, packet.payout_amount()?)?;
+    COMPLETED_TRANSFERS.remove(deps.storage, (&packet.channel_id.clone(), packet.sequence))?;
+    packet.transition(ibc::PacketLifecycleStatus::SendingExternalTokens)?;
+    pay_out(deps, packet)
 }
 
 /// Transfers any received INFLIGHT_PACKETS tokens to sender.
 pub fn send_external_tokens(deps: DepsMut, mut packet: IBCTransfer) -> Result<Response, ContractError> {
-    // TODO: check that we don't have a context state already set
-    packet.status = ibc::PacketLifecycleStatus::SendingExternalTokens;
-    packet.amount = packet.amount.mul(2);
-    SEND_EXTERNAL_TOKENS_REPLY_STATE.save(deps.storage, &packet)?;
-
-    let msg = BankMsg::Send {
-        to_address: packet.recovery_addr.to_string(),
-        amount: coins(packet.amount, packet.denom),
-    };
+    check_payout_unlocked(deps.storage)?;
+    packet.transition(ibc::PacketLifecycleStatus::SendingExternalTokens)?;
+    // The packet is no longer awaiting a response so it can't expire anymore
+    if let Some(deadline) = packet.response_deadline {
+        RESPONSE_DEADLINES.remove(deps.storage, (deadline.seconds(), &packet.channel_id, packet.sequence));
+    }
+    payout_queue::dequeue(deps.storage, &packet)?;
+    ledger::sub_in_flight(deps.storage, &packet.denom, packet.amount)?;
+    stats::settled(deps.storage, &packet)?;
+    pay_out(deps, packet)
+}
+
+/// Only one payout can be waiting for its reply at a time
+fn check_payout_unlocked(storage: &dyn Storage) -> Result<(), ContractError> {
+    if SEND_EXTERNAL_TOKENS_REPLY_STATE.may_load(storage)?.is_some() {
+        return Err(ContractError::ContractLocked {
+            msg: "Already waiting for a reply".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Sends the payout of a packet in SendingExternalTokens to its user
+fn pay_out(deps: DepsMut, packet: IBCTransfer) -> Result<Response, ContractError> {
+    let payout = coin(packet.payout_amount()?, packet.return_denom.clone());
+
+    let msg = payout_msg(&packet.asset, &packet.recovery_addr, payout.clone())?;
+    SEND_EXTERNAL_TOKENS_REPLY_STATE.save(deps.storage, &PayoutReplyState { packet, payout })?;
     // create reply context
     Ok(Response::new().add_submessage(SubMsg::reply_always(msg, MsgReplyID::SendAddr.repr())))
 }
 
 /// Transfers any tokens stored in RECOVERY_STATES [sender] to the sender.
-pub fn recover(deps: DepsMut, sender: Addr) -> Result<Response, ContractError> {
+/// Recovered transfers are moved to the archive. Recoveries the contract holds no funds for
+/// (expired responses stored before they were archived right away) are archived with nothing paid.
+pub fn recover(deps: DepsMut, env: Env, sender: Addr) -> Result<Response, ContractError> {
     let recoveries = RECOVERY_STATES.load(deps.storage, &sender)?;
+    let (funded, unfunded): (Vec<_>, Vec<_>) = recoveries
+        .into_iter()
+        .partition(|r| r.is_funded_recovery());
     // Remove the recoveries from the store. If the sends fail, the whole tx should be reverted.
     RECOVERY_STATES.remove(deps.storage, &sender);
-    let msgs = recoveries.into_iter().map(|r| BankMsg::Send {
-        to_address: r.recovery_addr.into(),
-        amount: coins(r.amount, r.denom),
-    });
+    for record in unfunded {
+        let nothing = coin(0, record.return_denom.clone());
+        ibc_lifecycle::archive(deps.storage, record, nothing)?;
+    }
+    let msgs = funded
+        .into_iter()
+        .map(|r| pay_recovery(deps.storage, &env, r))
+        .collect::<Result<Vec<_>, _>>()?;
     Ok(Response::new().add_messages(msgs))
 }
 
+/// The sender of a packet that was sent but not acked yet cancels the transfer.
+///
+/// ICS-20 has no way to call back a packet once it was sent: it is either received, or
+/// its funds come back with a failed ack or a timeout. The packet is marked so that its
+/// funds are refunded as soon as they come back, without waiting for RecoverToken. If the
+/// packet is delivered anyway, the cancellation has no effect.
+pub fn cancel_transfer(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    sequence: u64,
+) -> Result<Response, ContractError> {
+    let mut packet = INFLIGHT_PACKETS
+        .may_load(deps.storage, (&channel, sequence))?
+        .ok_or_else(|| ContractError::NoInflightPacket {
+            channel_id: channel.clone(),
+            sequence,
+        })?;
+    if packet.recovery_addr != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+    if packet.status != ibc::PacketLifecycleStatus::Sent {
+        return Err(ContractError::InvalidInflightPacketState {
+            channel_id: channel,
+            sequence,
+            status: packet.status.to_string(),
+        });
+    }
+    packet.cancel_requested = true;
+    INFLIGHT_PACKETS.save(deps.storage, (&channel, sequence), &packet)?;
+    Ok(Response::new()
+        .add_attribute("action", "cancel_transfer")
+        .add_attribute("channel", channel)
+        .add_attribute("sequence", sequence.to_string())
+        .add_attribute("msg", "refunded on ack failure or timeout"))
+}
+
 /// Add in external address to send tokens to
 pub fn add_external_address (deps: DepsMut, info: MessageInfo, alias: String, addr: String) -> Result<Response, ContractError>{
     // add new external address into state
     validate_owner(deps.as_ref(), info.sender)?;
-    EXTERNAL_ADDRESSES.save(deps.storage, alias, &addr).map_err(|err| ContractError::Std(err)).map(|_| Response::default())
+    EXTERNAL_ADDRESSES.save(deps.storage, alias, &addr).map_err(ContractError::Std).map(|_| Response::default())
 }
 
 pub fn validate_owner(deps: Deps, addr: Addr) -> Result<(), ContractError> {
-    let owner = CONFIG.load(deps.storage).map_err(|err| ContractError::Std(err))?;
+    let owner = CONFIG.load(deps.storage).map_err(ContractError::Std)?;
 
     if owner.owner.ne(&addr) {
         Err(ContractError::Unauthorized {  })
@@ -296,12 +757,324 @@ pub fn validate_owner(deps: Deps, addr: Addr) -> Result<(), ContractError> {
 pub fn add_port (deps: DepsMut, info: MessageInfo, alias: String, addr: String) -> Result<Response, ContractError>{
     // add new external address into state
     validate_owner(deps.as_ref(), info.sender)?;
-    PORTS.save(deps.storage, alias, &addr).map_err(|err| ContractError::Std(err)).map(|_| Response::default())
+    PORTS.save(deps.storage, alias, &addr).map_err(ContractError::Std).map(|_| Response::default())
 }
 
 /// Add in channel to send tokens over
 pub fn add_channel (deps: DepsMut, info: MessageInfo, alias: String, channel_id: u32) -> Result<Response, ContractError>{
     // add new external address into state
     validate_owner(deps.as_ref(), info.sender)?;
-    CHANNELS.save(deps.storage, alias, &channel_id).map_err(|err| ContractError::Std(err)).map(|_| Response::default())
+    CHANNELS.save(deps.storage, alias, &channel_id).map_err(ContractError::Std).map(|_| Response::default())
+}
+
+/// Fails over the packets of a channel the counterparty closed and stops sends on it.
+/// Pages of at most `limit` packets are processed per call. Channels of the contract port
+/// are closed by `ibc_channel_close`, the owner only pages through them afterwards.
+pub fn close_channel(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    #[cfg(feature = "native-ibc")]
+    if crate::state::NATIVE_CHANNELS.has(deps.storage, &channel_id) && !CLOSED_CHANNELS.has(deps.storage, &channel_id) {
+        return Err(ContractError::NativeChannelOpen { channel_id });
+    }
+    fail_over_channel(deps, &env, &channel_id, limit)
+}
+
+/// Lets a channel closed by mistake be sent on again. Its unacked packets time out as
+/// TimedOut again, acked packets that were already archived stay archived.
+pub fn reopen_channel(deps: DepsMut, info: MessageInfo, channel_id: String) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    #[cfg(feature = "native-ibc")]
+    if crate::state::NATIVE_CHANNELS.has(deps.storage, &channel_id) && CLOSED_CHANNELS.has(deps.storage, &channel_id) {
+        return Err(ContractError::ChannelClosed { channel_id });
+    }
+    CLOSED_CHANNELS.remove(deps.storage, &channel_id);
+    Ok(Response::new()
+        .add_attribute("action", "reopen_channel")
+        .add_attribute("channel", channel_id))
+}
+
+/// Settles a packet left Sent by an anomalous callback with the outcome the owner established
+pub fn resolve_anomaly(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel_id: String,
+    sequence: u64,
+    acked: bool,
+) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    ibc_lifecycle::resolve_anomaly(deps, env, channel_id, sequence, acked)
+}
+
+/// Sets the route of a recipient alias. The memo of the route is built once to
+/// make sure packets sent on it fit the memo limit.
+pub fn set_route(deps: DepsMut, env: Env, info: MessageInfo, alias: String, config: RouteConfig) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    if let Some(cw20) = &config.cw20 {
+        deps.api.addr_validate(cw20.contract.as_str())?;
+        deps.api.addr_validate(cw20.adapter.as_str())?;
+    }
+    if let Some(payout) = &config.payout {
+        match &payout.rate {
+            RateSource::Fixed(rate) if rate.is_zero() => {
+                return Err(ContractError::ZeroPayoutRate {
+                    denom: payout.denom.clone(),
+                })
+            }
+            RateSource::Fixed(_) => {}
+            RateSource::PriceFeed => {
+                oracle::price_feed(deps.as_ref())?;
+            }
+        }
+    }
+    if let (Some(cap), Some(payout)) = (&config.max_exposure, &config.payout) {
+        if cap.denom != payout.denom {
+            return Err(ContractError::ExposureDenomMismatch {
+                route: alias,
+                denom: payout.denom.clone(),
+                cap_denom: cap.denom.clone(),
+            });
+        }
+    }
+    if let Some(quote) = &config.quote {
+        if quote.ttl == 0 {
+            return Err(ContractError::ZeroQuoteTtl {});
+        }
+        if let Some(accepter) = &quote.accepter {
+            deps.api.addr_validate(accepter.as_str())?;
+        }
+    }
+    // the largest id gives the longest memo
+    let correlation_id = config.return_hook.then_some(u64::MAX);
+    route_memo(&env, config.clone(), correlation_id)?;
+    ROUTES.save(deps.storage, alias.clone(), &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_route")
+        .add_attribute("alias", alias))
+}
+
+/// Sets the address that may manage the sender lists besides the owner
+pub fn set_access_manager(deps: DepsMut, info: MessageInfo, manager: Option<String>) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    let mut access = ACCESS_CONTROL.may_load(deps.storage)?.unwrap_or_default();
+    access.manager = manager.map(|manager| deps.api.addr_validate(&manager)).transpose()?;
+    ACCESS_CONTROL.save(deps.storage, &access)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_access_manager")
+        .add_attribute("manager", access.manager.map(String::from).unwrap_or_default()))
+}
+
+/// The owner and the access manager may edit the sender lists
+fn validate_access_manager(deps: Deps, addr: &Addr) -> Result<(), ContractError> {
+    let access = ACCESS_CONTROL.may_load(deps.storage)?.unwrap_or_default();
+    if access.manager.as_ref() == Some(addr) {
+        return Ok(());
+    }
+    validate_owner(deps, addr.clone())
+}
+
+/// Adds and removes addresses of a sender list
+fn update_address_list(
+    deps: &mut DepsMut,
+    list: &Map<&Addr, Empty>,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<(), ContractError> {
+    for address in add {
+        list.save(deps.storage, &deps.api.addr_validate(&address)?, &Empty {})?;
+    }
+    for address in remove {
+        list.remove(deps.storage, &deps.api.addr_validate(&address)?);
+    }
+    Ok(())
+}
+
+/// Edits the SendToken allowlist and turns it on or off
+pub fn update_allowlist(
+    mut deps: DepsMut,
+    info: MessageInfo,
+    enabled: Option<bool>,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    validate_access_manager(deps.as_ref(), &info.sender)?;
+    update_address_list(&mut deps, &SENDER_ALLOWLIST, add, remove)?;
+    let mut access = ACCESS_CONTROL.may_load(deps.storage)?.unwrap_or_default();
+    if let Some(enabled) = enabled {
+        access.allowlist_enabled = enabled;
+    }
+    ACCESS_CONTROL.save(deps.storage, &access)?;
+    Ok(Response::new()
+        .add_attribute("action", "update_allowlist")
+        .add_attribute("enabled", access.allowlist_enabled.to_string()))
+}
+
+/// Edits the SendToken denylist
+pub fn update_denylist(
+    mut deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    validate_access_manager(deps.as_ref(), &info.sender)?;
+    update_address_list(&mut deps, &SENDER_DENYLIST, add, remove)?;
+    Ok(Response::new().add_attribute("action", "update_denylist"))
+}
+
+/// Update the contract configuration
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    response_deadline: Option<u64>,
+) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    let mut config = CONFIG.load(deps.storage)?;
+    if let Some(response_deadline) = response_deadline {
+        config.response_deadline = response_deadline;
+    }
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new().add_attribute("action", "update_config"))
+}
+
+/// Sets the price feed, or removes it with None. Sends that need prices then fail with NoPriceFeed.
+pub fn set_price_feed(deps: DepsMut, info: MessageInfo, price_feed: Option<PriceFeedConfig>) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    if let Some(price_feed) = &price_feed {
+        deps.api.addr_validate(price_feed.contract.as_str())?;
+    }
+    let mut config = CONFIG.load(deps.storage)?;
+    config.price_feed = price_feed;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_price_feed")
+        .add_attribute("price_feed", config.price_feed.map(|feed| feed.contract.to_string()).unwrap_or_default()))
+}
+
+/// Turns AwaitingResponse packets whose response deadline has passed into recoveries.
+/// Anyone can call this; at most `limit` packets are processed per call.
+///
+/// If the insurance pool for the packet denom can cover the payout, the user is paid
+/// from the pool instead and the packet is kept in INSURED_PACKETS so that a late
+/// payment from the external address reimburses the pool.
+pub fn sweep_expired(mut deps: DepsMut, env: Env, limit: Option<u32>) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_SWEEP_LIMIT).min(MAX_SWEEP_LIMIT) as usize;
+    // Everything with a deadline strictly before the current block time has expired
+    let expired = RESPONSE_DEADLINES
+        .keys(
+            deps.storage,
+            None,
+            Some(Bound::exclusive((env.block.time.seconds(), "", 0))),
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut swept = 0u32;
+    let mut insured = 0u32;
+    let mut outcomes = vec![];
+    for (deadline, channel_id, sequence) in expired {
+        RESPONSE_DEADLINES.remove(deps.storage, (deadline, &channel_id, sequence));
+        let Some(packet) = INFLIGHT_PACKETS.may_load(deps.storage, (&channel_id, sequence))? else {
+            continue
+        };
+        if packet.status != ibc::PacketLifecycleStatus::AwaitingResponse {
+            continue;
+        }
+        INFLIGHT_PACKETS.remove(deps.storage, (&channel_id, sequence))?;
+        payout_queue::dequeue(deps.storage, &packet)?;
+        ledger::sub_in_flight(deps.storage, &packet.denom, packet.amount)?;
+        stats::settled(deps.storage, &packet)?;
+        swept += 1;
+
+        let payout = packet.payout_amount()?;
+        // the payout is made in the denom the external address pays back in
+        let pool = INSURANCE_POOLS.may_load(deps.storage, &packet.return_denom)?.unwrap_or_default();
+        if pool < payout {
+            let (_, outcome) = create_recovery(deps.branch(), &env, packet, ibc::PacketLifecycleStatus::ResponseExpired)?;
+            outcomes.extend(outcome);
+            continue;
+        }
+        INSURANCE_POOLS.save(deps.storage, &packet.return_denom, &(pool - payout))?;
+        let mut packet = packet;
+        packet.transition(ibc::PacketLifecycleStatus::ResponseExpired)?;
+        INSURED_PACKETS.save(deps.storage, (&channel_id, sequence), &packet)?;
+        let (_, outcome) = create_recovery(
+            deps.branch(),
+            &env,
+            IBCTransfer {
+                amount: payout,
+                denom: packet.return_denom.clone(),
+                ..packet
+            },
+            ibc::PacketLifecycleStatus::InsuredPayout,
+        )?;
+        outcomes.extend(outcome);
+        insured += 1;
+    }
+
+    Ok(Response::new()
+        .add_submessages(outcomes)
+        .add_attribute("action", "sweep_expired")
+        .add_attribute("swept", swept.to_string())
+        .add_attribute("insured", insured.to_string()))
+}
+
+/// Adds the attached funds to the insurance pool
+pub fn deposit_insurance(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender)?;
+    if info.funds.is_empty() {
+        return Err(ContractError::EmptyInsuranceDeposit);
+    }
+    for coin in info.funds.iter() {
+        INSURANCE_POOLS.update(deps.storage, &coin.denom, |balance| {
+            Ok::<_, ContractError>(ledger::checked_add(balance.unwrap_or_default(), coin.amount.u128())?)
+        })?;
+    }
+    Ok(Response::new()
+        .add_attribute("action", "deposit_insurance")
+        .add_attribute("amount", format!("{:?}", info.funds)))
+}
+
+/// Sends funds from the insurance pool back to the owner
+pub fn withdraw_insurance(deps: DepsMut, info: MessageInfo, amount: Coin) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender.clone())?;
+    let balance = INSURANCE_POOLS.may_load(deps.storage, &amount.denom)?.unwrap_or_default();
+    if balance < amount.amount.u128() {
+        return Err(ContractError::InsufficientPoolFunds {
+            denom: amount.denom,
+            available: balance,
+            requested: amount.amount.u128(),
+        });
+    }
+    INSURANCE_POOLS.save(deps.storage, &amount.denom, &(balance - amount.amount.u128()))?;
+    Ok(Response::new()
+        .add_attribute("action", "withdraw_insurance")
+        .add_attribute("amount", amount.to_string())
+        .add_message(BankMsg::Send {
+            to_address: info.sender.into(),
+            amount: vec![amount],
+        }))
+}
+
+/// Sends the part of the contract balance of `denom` that is not owed to users or
+/// held in the insurance pool to the owner
+pub fn sweep_surplus(deps: DepsMut, env: Env, info: MessageInfo, denom: String) -> Result<Response, ContractError> {
+    validate_owner(deps.as_ref(), info.sender.clone())?;
+    let reconciliation = crate::query::reconcile(deps.as_ref(), env, denom.clone())?;
+    if reconciliation.surplus.is_zero() {
+        return Err(ContractError::NoSurplus { denom });
+    }
+    Ok(Response::new()
+        .add_attribute("action", "sweep_surplus")
+        .add_attribute("amount", reconciliation.surplus.to_string())
+        .add_message(BankMsg::Send {
+            to_address: info.sender.into(),
+            amount: coins(reconciliation.surplus.u128(), denom),
+        }))
 }
@@ -1,32 +1,266 @@
-use cosmwasm_std::{Addr, DepsMut, Response};
+use cosmwasm_std::{
+    coin, Addr, Coin, CosmosMsg, DepsMut, Empty, Env, Event, Order, Response, StdResult, Storage, SubMsg,
+};
+use sha2::{Digest, Sha256};
 
-use crate::state::{self, ibc};
+use crate::asset::payout_msg;
+use crate::callback;
+use crate::{ledger, memo, payout_queue, stats};
+use crate::state::{self, ibc, ibc::BlockStamp, ibc::CompletedTransfer};
+use crate::consts::{DEFAULT_SWEEP_LIMIT, MAX_SWEEP_LIMIT};
 use crate::{
-    state::{INFLIGHT_PACKETS, RECOVERY_STATES},
+    state::{CLOSED_CHANNELS, COMPLETED_TRANSFERS, CONFIG, INFLIGHT_PACKETS, PROCESSED_CALLBACKS, RECOVERY_STATES, RESPONSE_DEADLINES},
     ContractError,
 };
 
-// Store a RECOVERY_STATE for the failed ibc packet
+/// Hex sha256 of the fields of a sent transfer packet
+pub fn packet_hash(denom: &str, amount: u128, receiver: &str, memo: &str) -> String {
+    let fields = (denom, amount.to_string(), receiver, memo);
+    // a tuple of strings always serializes
+    let data = serde_json_wasm::to_vec(&fields).unwrap_or_default();
+    hex::encode(Sha256::digest(data))
+}
+
+/// Where a lifecycle callback comes from, which decides what it is checked against
+#[derive(Clone, Copy)]
+pub enum CallbackSource<'a> {
+    /// ibc-hooks sudo callback, it only carries the channel and sequence
+    Hooks,
+    /// Native IBC ack or timeout, with the packet_hash of the packet data it delivered
+    #[cfg_attr(not(feature = "native-ibc"), allow(dead_code))]
+    Native(&'a str),
+    /// The owner settling an anomaly with ResolveAnomaly
+    Owner,
+}
+
+/// Why a lifecycle callback for `packet` can't be trusted, if it can't. A native callback
+/// delivers the packet data, whose hash must match the hash stored on send. ibc-hooks calls
+/// back the contract named by the `ibc_callback` of the packet memo, so for a sudo callback
+/// the stored packet must still hash to the stored hash and its memo must name this
+/// contract. Packets recorded without a hash can't be verified and are anomalies too.
+fn anomaly(env: &Env, packet: &ibc::IBCTransfer, source: CallbackSource) -> Option<&'static str> {
+    if matches!(source, CallbackSource::Owner) {
+        return None;
+    }
+    let Some(expected) = packet.packet_hash.as_deref() else {
+        return Some("packet has no hash to verify the callback against");
+    };
+    match source {
+        CallbackSource::Native(delivered) => {
+            (delivered != expected).then_some("delivered packet does not match the sent packet")
+        }
+        _ if packet_hash(&packet.denom, packet.amount, &packet.receiver, &packet.memo) != expected => {
+            Some("sent packet does not match its hash")
+        }
+        _ if memo::ibc_callback(&packet.memo).as_deref() != Some(env.contract.address.as_str()) => {
+            Some("packet memo does not call back this contract")
+        }
+        _ => None,
+    }
+}
+
+/// Records `reason` on the packet so that the owner can resolve it with ResolveAnomaly
+fn anomaly_response(
+    storage: &mut dyn Storage,
+    response: Response,
+    mut packet: ibc::IBCTransfer,
+    reason: &str,
+) -> StdResult<Response> {
+    let (channel, sequence) = (packet.channel_id.clone(), packet.sequence);
+    packet.anomaly = Some(reason.to_string());
+    INFLIGHT_PACKETS.save(storage, (&channel, sequence), &packet)?;
+    Ok(response
+        .add_attribute("msg", "anomaly")
+        .add_event(
+            Event::new("ibc_transfer_anomaly")
+                .add_attribute("channel", channel)
+                .add_attribute("sequence", sequence.to_string())
+                .add_attribute("reason", reason),
+        ))
+}
+
+/// The callback of a packet that already had its ack or timeout processed changes nothing
+fn duplicate_response(response: Response, processed: &ibc::LifecycleCallback) -> Response {
+    response
+        .add_attribute("msg", "duplicate callback")
+        .add_attribute("processed", processed.to_string())
+}
+
+// Store a RECOVERY_STATE for the failed ibc packet. When the recovery status is terminal the
+// outcome callback of the packet is returned with the recovery address; an expired response
+// is only reported once it is paid late or insured.
+// Recoveries the contract holds no funds for have nothing to claim and go straight to the
+// archive, where a late payment from the external address can still find them.
 pub fn create_recovery(
     deps: DepsMut,
+    env: &Env,
     inflight_packet: state::ibc::IBCTransfer,
     recovery_reason: state::ibc::PacketLifecycleStatus,
-) -> Result<Addr, ContractError> {
+) -> Result<(Addr, Option<SubMsg>), ContractError> {
     let mut recovery = inflight_packet; // Recoveries are just inflight packets ready to be recovered
     let recovery_addr = recovery.recovery_addr.clone();
+    recovery.transition(recovery_reason)?;
+    recovery.timeline.failed = Some(BlockStamp::now(env));
+    // A failed payout was never paid, it is only left to claim with RecoverToken
+    let paid = recovery.is_funded_recovery() && recovery.status != ibc::PacketLifecycleStatus::SendingExternalTokensFailure;
+    let payout = paid.then(|| coin(recovery.amount, recovery.denom.clone()));
+    let outcome = if recovery.status.is_terminal() { callback::outcome_msg(&recovery, payout)? } else { None };
+    if !recovery.is_funded_recovery() {
+        let nothing = coin(0, recovery.return_denom.clone());
+        archive(deps.storage, recovery, nothing)?;
+        return Ok((recovery_addr, outcome));
+    }
+    ledger::add_pending_recovery(deps.storage, &recovery.denom, recovery.amount)?;
+    recovery.timeline.recovered = Some(BlockStamp::now(env));
 
     RECOVERY_STATES.update(deps.storage, &recovery_addr, |recoveries| {
         // Since the recovery state and the in-flight packet store the same
         // data, we can just modify the status and store the object in the
         // RECOVERY_STATES map.
-        recovery.status = recovery_reason;
         let Some(mut recoveries) = recoveries else {
             return Ok::<_, ContractError>(vec![recovery])
         };
         recoveries.push(recovery);
         Ok(recoveries)
     })?;
-    Ok(recovery_addr)
+    Ok((recovery_addr, outcome))
+}
+
+/// Pays a funded recovery out to its user and moves it to the archive
+pub fn pay_recovery(storage: &mut dyn Storage, env: &Env, recovery: ibc::IBCTransfer) -> Result<CosmosMsg, ContractError> {
+    ledger::sub_pending_recovery(storage, &recovery.denom, recovery.amount)?;
+    stats::recovered(storage, &recovery)?;
+    let payout = coin(recovery.amount, recovery.denom.clone());
+    let msg = payout_msg(&recovery.asset, &recovery.recovery_addr, payout.clone())?;
+    let mut transfer = recovery;
+    transfer.timeline.claimed = Some(BlockStamp::now(env));
+    archive(storage, transfer, payout)?;
+    Ok(msg)
+}
+
+/// Moves a transfer that is no longer tracked to the archive with what was finally sent to its user.
+/// Its processed callback is dropped, a late callback finds no packet and changes nothing.
+pub fn archive(storage: &mut dyn Storage, transfer: ibc::IBCTransfer, payout: Coin) -> StdResult<()> {
+    PROCESSED_CALLBACKS.remove(storage, (&transfer.channel_id, transfer.sequence));
+    COMPLETED_TRANSFERS.save(
+        storage,
+        (&transfer.channel_id.clone(), transfer.sequence),
+        &CompletedTransfer { transfer, payout },
+    )
+}
+
+/// Drops the processed callbacks of archived transfers, recorded before archive pruned them
+pub fn prune_processed_callbacks(storage: &mut dyn Storage) -> StdResult<()> {
+    let archived = PROCESSED_CALLBACKS
+        .keys(storage, None, None, Order::Ascending)
+        .filter(|key| !matches!(key, Ok((channel, sequence)) if !COMPLETED_TRANSFERS.has(storage, (channel, *sequence))))
+        .collect::<StdResult<Vec<_>>>()?;
+    for (channel, sequence) in archived {
+        PROCESSED_CALLBACKS.remove(storage, (&channel, sequence));
+    }
+    Ok(())
+}
+
+/// Sets the return denom of packets and recoveries recorded before it was stored. The
+/// external address paid those back in the denom that was sent.
+pub fn backfill_return_denoms(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy = |packet: &mut ibc::IBCTransfer| {
+        let missing = packet.return_denom.is_empty();
+        if missing {
+            packet.return_denom = packet.denom.clone();
+        }
+        missing
+    };
+    let packets = INFLIGHT_PACKETS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for ((channel, sequence), mut packet) in packets {
+        if legacy(&mut packet) {
+            INFLIGHT_PACKETS.save(storage, (&channel, sequence), &packet)?;
+        }
+    }
+    let recoveries = RECOVERY_STATES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (addr, mut recoveries) in recoveries {
+        let mut missing = false;
+        for recovery in recoveries.iter_mut() {
+            missing |= legacy(recovery);
+        }
+        if missing {
+            RECOVERY_STATES.save(storage, &addr, &recoveries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves every in-flight packet again so that the user index has an entry for each of them
+pub fn reindex_inflight_packets(storage: &mut dyn Storage) -> StdResult<()> {
+    let packets = INFLIGHT_PACKETS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for ((channel, sequence), packet) in packets {
+        INFLIGHT_PACKETS.save(storage, (&channel, sequence), &packet)?;
+    }
+    Ok(())
+}
+
+/// A packet that failed, with the messages to send for it
+struct FailedPacket {
+    recovery_addr: Addr,
+    /// Refund of a cancelled transfer
+    refund: Option<CosmosMsg>,
+    outcome: Option<SubMsg>,
+}
+
+/// Stores the recovery of a packet whose ack failed or that timed out. Only these callbacks
+/// bring its funds back, so a cancelled transfer is refunded right away here and nowhere else.
+fn fail_packet(
+    mut deps: DepsMut,
+    env: &Env,
+    packet: ibc::IBCTransfer,
+    reason: ibc::PacketLifecycleStatus,
+) -> Result<FailedPacket, ContractError> {
+    let (cancelled, channel_id, sequence) = (packet.cancel_requested, packet.channel_id.clone(), packet.sequence);
+    let (recovery_addr, outcome) = create_recovery(deps.branch(), env, packet, reason)?;
+    let mut failed = FailedPacket {
+        recovery_addr,
+        refund: None,
+        outcome,
+    };
+    if !cancelled {
+        return Ok(failed);
+    }
+    let recovery_addr = &failed.recovery_addr;
+    let mut recoveries = RECOVERY_STATES.load(deps.storage, recovery_addr)?;
+    let Some(position) = recoveries
+        .iter()
+        .position(|r| r.channel_id == channel_id && r.sequence == sequence && r.is_funded_recovery())
+    else {
+        return Ok(failed);
+    };
+    let recovery = recoveries.remove(position);
+    if recoveries.is_empty() {
+        RECOVERY_STATES.remove(deps.storage, recovery_addr);
+    } else {
+        RECOVERY_STATES.save(deps.storage, recovery_addr, &recoveries)?;
+    }
+    failed.refund = Some(pay_recovery(deps.storage, env, recovery)?);
+    Ok(failed)
+}
+
+/// Response of a packet that failed, with the refund of a cancelled transfer
+fn failed_response(response: Response, failed: FailedPacket) -> Response {
+    let response = response.add_submessages(failed.outcome);
+    match failed.refund {
+        Some(refund) => response
+            .add_message(refund)
+            .add_attribute("msg", "cancelled transfer refunded")
+            .add_attribute("recovery_addr", failed.recovery_addr),
+        None => response
+            .add_attribute("msg", "recovery stored")
+            .add_attribute("recovery_addr", failed.recovery_addr),
+    }
 }
 
 /// Called by the chain when the ack for a packet that has configured this contract as its
@@ -41,18 +275,36 @@ pub fn create_recovery(
 /// If this contract sent the IBC packet, its data will be stored in
 /// INFLIGHT_PACKETS. At this point the ack can be a success or a failure.
 ///
-/// If it's a success, we remove the inflight packet and return. The packet will
-/// no longer be tracked.
+/// If it's a success, the packet is kept as AwaitingResponse until the external
+/// address pays back or the configured response deadline passes.
 ///
 /// If it's a failure, the sent funds will have been returned to this contract.
 /// We then store the amount and original sender on RECOVERY_STATES so that the
 /// sender can recover the funds by calling execute::Recover{}.
+///
+/// A callback that can't be verified against what this contract sent (see `anomaly`) is
+/// not acted on, an `ibc_transfer_anomaly` event is emitted instead and the packet stays
+/// Sent until the owner resolves it with `resolve_anomaly`.
 pub fn receive_ack(
     deps: DepsMut,
+    env: Env,
     source_channel: String,
     sequence: u64,
     ack: String,
     success: bool,
+) -> Result<Response, ContractError> {
+    receive_packet_ack(deps, env, source_channel, sequence, ack, success, CallbackSource::Hooks)
+}
+
+/// receive_ack for callbacks of any source
+pub fn receive_packet_ack(
+    mut deps: DepsMut,
+    env: Env,
+    source_channel: String,
+    sequence: u64,
+    ack: String,
+    success: bool,
+    source: CallbackSource,
 ) -> Result<Response, ContractError> {
     deps.api.debug(&format!(
         "received ack for packet {source_channel:?} {sequence:?}: {ack:?}, {success:?}"
@@ -61,63 +313,212 @@ pub fn receive_ack(
         .add_attribute("contract", "ibc_transfer")
         .add_attribute("action", "receive_ack");
 
+    if let Some(processed) = PROCESSED_CALLBACKS.may_load(deps.storage, (&source_channel, sequence))? {
+        return Ok(duplicate_response(response, &processed));
+    }
+
     // Check if there is an inflight packet for the received (channel, sequence)
     let sent_packet = INFLIGHT_PACKETS.may_load(deps.storage, (&source_channel, sequence))?;
     if let Some(mut inflight_packet) = sent_packet {
+        if let Some(reason) = anomaly(&env, &inflight_packet, source) {
+            return Ok(anomaly_response(deps.storage, response, inflight_packet, reason)?);
+        }
+        PROCESSED_CALLBACKS.save(
+            deps.storage,
+            (&source_channel, sequence),
+            &ibc::LifecycleCallback::Ack { success },
+        )?;
         if success {
-            // Update the in-flight packet state to AwaitingResponse
-            inflight_packet.status = ibc::PacketLifecycleStatus::AwaitingResponse;
+            // Update the in-flight packet state to AwaitingResponse and start the response deadline
+            let config = CONFIG.load(deps.storage)?;
+            let deadline = env.block.time.plus_seconds(config.response_deadline);
+            inflight_packet.transition(ibc::PacketLifecycleStatus::AckSuccess)?;
+            inflight_packet.transition(ibc::PacketLifecycleStatus::AwaitingResponse)?;
+            inflight_packet.response_deadline = Some(deadline);
+            inflight_packet.timeline.acked = Some(BlockStamp::now(&env));
             INFLIGHT_PACKETS.save(deps.storage, (&source_channel, sequence), &inflight_packet)?;
-            // If the acc is successful, there is nothing else to do and the transfer has been completed
-            return Ok(response.add_attribute("msg", "packet successfully delivered"));
+            stats::acked(deps.storage, &inflight_packet)?;
+            payout_queue::enqueue(deps.storage, &inflight_packet)?;
+            RESPONSE_DEADLINES.save(
+                deps.storage,
+                (deadline.seconds(), &source_channel, sequence),
+                &Empty {},
+            )?;
+            // If the acc is successful, there is nothing else to do and the transfer has been completed.
+            // A cancellation came too late, the tokens were delivered.
+            let response = response.add_attribute("msg", "packet successfully delivered");
+            if inflight_packet.cancel_requested {
+                return Ok(response.add_attribute("cancel", "delivered before the cancellation"));
+            }
+            return Ok(response);
         }
         // If the ack is a failure, we create a recovery for the original sender of the packet.
-        // Remove the in-flight packet
-        INFLIGHT_PACKETS.remove(deps.storage, (&source_channel, sequence));
-
-        let recovery_addr = create_recovery(
-            deps,
+        let (denom, amount) = (inflight_packet.denom.clone(), inflight_packet.amount);
+        stats::ack_failed(deps.storage, &inflight_packet)?;
+        let failed = fail_packet(
+            deps.branch(),
+            &env,
             inflight_packet,
             state::ibc::PacketLifecycleStatus::AckFailure,
         )?;
 
-        Ok(response
-            .add_attribute("msg", "recovery stored")
-            .add_attribute("recovery_addr", recovery_addr))
+        // Remove the in-flight packet
+        INFLIGHT_PACKETS.remove(deps.storage, (&source_channel, sequence))?;
+        ledger::sub_in_flight(deps.storage, &denom, amount)?;
+
+        Ok(failed_response(response, failed))
     } else {
         // If there isn't, continue
-        return Ok(response.add_attribute("msg", "received unexpected ack"))
+        Ok(response.add_attribute("msg", "received unexpected ack"))
+    }
+}
+
+/// Applies the outcome the owner established for a Sent packet whose callback was an
+/// anomaly: a successful ack when `acked`, otherwise a timeout that refunded its tokens.
+pub fn resolve_anomaly(
+    mut deps: DepsMut,
+    env: Env,
+    channel_id: String,
+    sequence: u64,
+    acked: bool,
+) -> Result<Response, ContractError> {
+    let packet = INFLIGHT_PACKETS
+        .may_load(deps.storage, (&channel_id, sequence))?
+        .ok_or_else(|| ContractError::NoInflightPacket {
+            channel_id: channel_id.clone(),
+            sequence,
+        })?;
+    if packet.anomaly.is_none() || packet.status != ibc::PacketLifecycleStatus::Sent {
+        return Err(ContractError::NoAnomaly { channel_id, sequence });
+    }
+    let response = if acked {
+        let ack = "resolved by the owner".to_string();
+        receive_packet_ack(deps.branch(), env, channel_id.clone(), sequence, ack, true, CallbackSource::Owner)?
+    } else {
+        receive_packet_timeout(deps.branch(), env, channel_id.clone(), sequence, CallbackSource::Owner)?
+    };
+    // the tokens of a native packet that never arrived leave the escrow
+    #[cfg(feature = "native-ibc")]
+    if !acked && state::NATIVE_CHANNELS.has(deps.storage, &channel_id) {
+        ledger::sub_escrowed(deps.storage, &packet.denom, packet.amount)?;
     }
+    Ok(response.add_attribute("resolved", "anomaly"))
 }
 
 // This is very similar to the handling of acks, but it always creates a
 // recovery since there is no concept of a "successful timeout"
 pub fn receive_timeout(
     deps: DepsMut,
+    env: Env,
     source_channel: String,
     sequence: u64,
+) -> Result<Response, ContractError> {
+    receive_packet_timeout(deps, env, source_channel, sequence, CallbackSource::Hooks)
+}
+
+/// receive_timeout for callbacks of any source
+pub fn receive_packet_timeout(
+    mut deps: DepsMut,
+    env: Env,
+    source_channel: String,
+    sequence: u64,
+    source: CallbackSource,
 ) -> Result<Response, ContractError> {
     let response = Response::new()
         .add_attribute("contract", "ibc transfer")
         .add_attribute("action", "receive_timeout");
 
+    if let Some(processed) = PROCESSED_CALLBACKS.may_load(deps.storage, (&source_channel, sequence))? {
+        return Ok(duplicate_response(response, &processed));
+    }
+
     // Check if there is an inflight packet for the received (channel, sequence)
     let sent_packet = INFLIGHT_PACKETS.may_load(deps.storage, (&source_channel, sequence))?;
     let Some(inflight_packet) = sent_packet else {
         // If there isn't, continue
         return Ok(response.add_attribute("msg", "received unexpected timeout"))
     };
-    // Remove the in-flight packet
-    INFLIGHT_PACKETS.remove(deps.storage, (&source_channel, sequence));
+    if let Some(reason) = anomaly(&env, &inflight_packet, source) {
+        return Ok(anomaly_response(deps.storage, response, inflight_packet, reason)?);
+    }
+    let (denom, amount) = (inflight_packet.denom.clone(), inflight_packet.amount);
+
+    PROCESSED_CALLBACKS.save(deps.storage, (&source_channel, sequence), &ibc::LifecycleCallback::Timeout)?;
+    // the timeout of a packet whose channel closed is the refund fail_over_channel waited for
+    let reason = if inflight_packet.channel_closed && CLOSED_CHANNELS.has(deps.storage, &source_channel) {
+        stats::unacked_closed(deps.storage, &inflight_packet)?;
+        state::ibc::PacketLifecycleStatus::ChannelClosed
+    } else {
+        stats::timed_out(deps.storage, &inflight_packet)?;
+        state::ibc::PacketLifecycleStatus::TimedOut
+    };
 
     // create a recovery
-    let recovery_addr = create_recovery(
-        deps,
-        inflight_packet,
-        state::ibc::PacketLifecycleStatus::TimedOut,
-    )?;
+    let failed = fail_packet(deps.branch(), &env, inflight_packet, reason)?;
 
-    Ok(response
-        .add_attribute("msg", "recovery stored")
-        .add_attribute("recovery_addr", recovery_addr))
+    // Remove the in-flight packet
+    INFLIGHT_PACKETS.remove(deps.storage, (&source_channel, sequence))?;
+    ledger::sub_in_flight(deps.storage, &denom, amount)?;
+
+    Ok(failed_response(response, failed))
+}
+
+/// Called when the counterparty closed `channel_id`. The channel can no longer be sent on and
+/// up to `limit` of its outstanding packets are failed over, call again while `more` is true.
+///
+/// Packets that were not acked yet are only marked `channel_closed`: their tokens come back
+/// with the timeout the chain delivers on close, which turns them into a ChannelClosed
+/// recovery that can be claimed. Acked packets are with the external address and are
+/// archived, like expired responses.
+pub fn fail_over_channel(
+    mut deps: DepsMut,
+    env: &Env,
+    channel_id: &str,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    CLOSED_CHANNELS.save(deps.storage, channel_id, &Empty {})?;
+    let limit = limit.unwrap_or(DEFAULT_SWEEP_LIMIT).min(MAX_SWEEP_LIMIT) as usize;
+    let closed = ibc::PacketLifecycleStatus::ChannelClosed;
+    let mut packets = INFLIGHT_PACKETS
+        .prefix(channel_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| match item {
+            Ok((_, packet)) => match packet.status {
+                ibc::PacketLifecycleStatus::Sent => !packet.channel_closed,
+                _ => packet.status.can_transition_to(&closed),
+            },
+            Err(_) => true,
+        })
+        .take(limit + 1)
+        .collect::<StdResult<Vec<_>>>()?;
+    let more = packets.len() > limit;
+    packets.truncate(limit);
+
+    let failed_over = packets.len();
+    let mut msgs = vec![];
+    for (sequence, mut packet) in packets {
+        if packet.status == ibc::PacketLifecycleStatus::Sent {
+            packet.channel_closed = true;
+            INFLIGHT_PACKETS.save(deps.storage, (channel_id, sequence), &packet)?;
+            continue;
+        }
+        if let Some(deadline) = packet.response_deadline {
+            RESPONSE_DEADLINES.remove(deps.storage, (deadline.seconds(), channel_id, sequence));
+        }
+        payout_queue::dequeue(deps.storage, &packet)?;
+        stats::settled(deps.storage, &packet)?;
+        let (denom, amount) = (packet.denom.clone(), packet.amount);
+        // the tokens are with the external address, there is nothing to refund a cancellation with
+        let (_, outcome) = create_recovery(deps.branch(), env, packet, closed.clone())?;
+        msgs.extend(outcome);
+        INFLIGHT_PACKETS.remove(deps.storage, (channel_id, sequence))?;
+        ledger::sub_in_flight(deps.storage, &denom, amount)?;
+    }
+
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "fail_over_channel")
+        .add_attribute("channel", channel_id)
+        .add_attribute("failed_over", failed_over.to_string())
+        .add_attribute("more", more.to_string()))
 }
@@ -0,0 +1,136 @@
+use cosmwasm_std::{Order, StdResult, Storage, Uint128};
+
+use crate::state::ibc::PacketLifecycleStatus;
+use crate::state::{Ledger, INFLIGHT_PACKETS, LEDGERS, QUOTES, RECOVERY_STATES};
+
+/// Applies `action` to the ledger of `denom` and saves it
+fn update<F>(storage: &mut dyn Storage, denom: &str, action: F) -> StdResult<Ledger>
+where
+    F: FnOnce(&mut Ledger) -> StdResult<()>,
+{
+    let mut ledger = LEDGERS.may_load(storage, denom)?.unwrap_or_default();
+    action(&mut ledger)?;
+    LEDGERS.save(storage, denom, &ledger)?;
+    Ok(ledger)
+}
+
+/// `total + amount`. A figure that doesn't fit fails the tx like any other overflow.
+pub fn checked_add(total: u128, amount: u128) -> StdResult<u128> {
+    Ok(Uint128::new(total).checked_add(Uint128::new(amount))?.u128())
+}
+
+/// `total - amount`. Taking out more than was put in is a bookkeeping bug and fails the tx,
+/// it would otherwise show up as surplus that SweepSurplus moves out.
+fn checked_sub(total: u128, amount: u128) -> StdResult<u128> {
+    Ok(Uint128::new(total).checked_sub(Uint128::new(amount))?.u128())
+}
+
+/// Tokens sent over IBC for a packet that is now in INFLIGHT_PACKETS
+pub fn add_in_flight(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.in_flight = checked_add(l.in_flight, amount)?;
+        Ok(())
+    })
+}
+
+/// A packet left INFLIGHT_PACKETS
+pub fn sub_in_flight(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.in_flight = checked_sub(l.in_flight, amount)?;
+        Ok(())
+    })
+}
+
+/// Tokens sent over a native IBC channel and kept in escrow
+#[cfg(feature = "native-ibc")]
+pub fn add_escrowed(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.escrowed = checked_add(l.escrowed, amount)?;
+        Ok(())
+    })
+}
+
+/// Escrowed tokens came back with a failed ack or a timeout, or their vouchers were returned
+#[cfg(feature = "native-ibc")]
+pub fn sub_escrowed(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.escrowed = checked_sub(l.escrowed, amount)?;
+        Ok(())
+    })
+}
+
+/// Funds of a SendToken held in a quote
+pub fn add_quoted(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.quoted = checked_add(l.quoted, amount)?;
+        Ok(())
+    })
+}
+
+/// A quote was accepted or refunded
+pub fn sub_quoted(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.quoted = checked_sub(l.quoted, amount)?;
+        Ok(())
+    })
+}
+
+/// Funds held by the contract that a user can claim with RecoverToken
+pub fn add_pending_recovery(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.pending_recoveries = checked_add(l.pending_recoveries, amount)?;
+        Ok(())
+    })
+}
+
+/// A recovery was paid out to its user
+pub fn sub_pending_recovery(storage: &mut dyn Storage, denom: &str, amount: u128) -> StdResult<Ledger> {
+    update(storage, denom, |l| {
+        l.pending_recoveries = checked_sub(l.pending_recoveries, amount)?;
+        Ok(())
+    })
+}
+
+/// Recomputes the in-flight, pending recovery and quoted figures of every denom from the
+/// packets, recoveries and quotes in storage. Run on migration so that the checked
+/// decrements hold for what was tracked before (or without) the ledger. Escrow is kept.
+pub fn rebuild(storage: &mut dyn Storage) -> StdResult<()> {
+    let mut ledgers = LEDGERS
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|(denom, ledger)| {
+                let ledger = Ledger {
+                    escrowed: ledger.escrowed,
+                    ..Default::default()
+                };
+                (denom, ledger)
+            })
+        })
+        .collect::<StdResult<std::collections::BTreeMap<_, _>>>()?;
+
+    for item in INFLIGHT_PACKETS.range(storage, None, None, Order::Ascending) {
+        let (_, packet) = item?;
+        // a packet being paid out already left the in-flight figure
+        if packet.status != PacketLifecycleStatus::SendingExternalTokens {
+            let ledger = ledgers.entry(packet.denom).or_default();
+            ledger.in_flight = checked_add(ledger.in_flight, packet.amount)?;
+        }
+    }
+    for item in RECOVERY_STATES.range(storage, None, None, Order::Ascending) {
+        let (_, recoveries) = item?;
+        for recovery in recoveries.into_iter().filter(|r| r.is_funded_recovery()) {
+            let ledger = ledgers.entry(recovery.denom).or_default();
+            ledger.pending_recoveries = checked_add(ledger.pending_recoveries, recovery.amount)?;
+        }
+    }
+    for item in QUOTES.range(storage, None, None, Order::Ascending) {
+        let (_, quote) = item?;
+        let ledger = ledgers.entry(quote.amount.denom).or_default();
+        ledger.quoted = checked_add(ledger.quoted, quote.amount.amount.u128())?;
+    }
+
+    for (denom, ledger) in ledgers {
+        LEDGERS.save(storage, &denom, &ledger)?;
+    }
+    Ok(())
+}
@@ -1,11 +1,23 @@
+mod asset;
+mod callback;
 pub mod contract;
 mod error;
 pub mod helpers;
 pub mod msg;
 pub mod state;
 mod execute;
+mod query;
 mod proto;
 mod consts;
+mod denom;
 mod ibc_lifecycle;
+mod ledger;
+mod payout_queue;
+mod quote;
+mod stats;
+pub mod memo;
+pub mod oracle;
+#[cfg(feature = "native-ibc")]
+pub mod native_ibc;
 
 pub use crate::error::ContractError;
\ No newline at end of file
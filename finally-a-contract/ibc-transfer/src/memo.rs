@@ -0,0 +1,136 @@
+use cosmwasm_schema::cw_serde;
+use schemars::_serde_json::{Map, Value};
+
+use crate::consts::MAX_MEMO_LENGTH;
+use crate::ContractError;
+
+/// A packet-forward-middleware hop: the chain receiving the packet forwards it
+/// to `receiver` over (`port`, `channel`)
+#[cw_serde]
+pub struct ForwardHop {
+    pub receiver: String,
+    pub port: String,
+    pub channel: String,
+    /// PFM duration string, e.g. "10m"
+    pub timeout: Option<String>,
+    pub retries: Option<u8>,
+}
+
+/// Builds the memo of an outgoing ICS-20 packet.
+///
+/// `forward` hops are nested through their `next` field, the `wasm` hook is
+/// executed on the chain the packet finally lands on (inside the last hop) and
+/// `ibc_callback` stays at the top level since it is read by ibc-hooks on this chain.
+#[derive(Default)]
+pub struct MemoBuilder {
+    ibc_callback: Option<String>,
+    wasm: Option<WasmHook>,
+    return_hook: Option<ReturnHook>,
+    forward: Vec<ForwardHop>,
+}
+
+/// Tells the external side how to pay a packet back: send the tokens back with
+/// `{"wasm": <wasm>}` as the memo
+pub struct ReturnHook {
+    pub correlation_id: u64,
+    pub wasm: WasmHook,
+}
+
+/// ibc-hooks `wasm` memo payload
+pub struct WasmHook {
+    pub contract: String,
+    pub msg: Value,
+}
+
+impl WasmHook {
+    fn into_value(self) -> Value {
+        let mut wasm = Map::new();
+        wasm.insert("contract".to_string(), Value::String(self.contract));
+        wasm.insert("msg".to_string(), self.msg);
+        Value::Object(wasm)
+    }
+}
+
+/// Contract the `ibc_callback` of `memo` names, if the memo is JSON and has one
+pub fn ibc_callback(memo: &str) -> Option<String> {
+    match serde_json_wasm::from_str::<Value>(memo).ok()?.get("ibc_callback")? {
+        Value::String(contract) => Some(contract.clone()),
+        _ => None,
+    }
+}
+
+impl MemoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Contract receiving the ack/timeout of the packet through sudo
+    pub fn ibc_callback(mut self, contract: impl Into<String>) -> Self {
+        self.ibc_callback = Some(contract.into());
+        self
+    }
+
+    /// ibc-hooks execute of `contract` with `msg` on the destination chain
+    pub fn wasm(mut self, contract: impl Into<String>, msg: Value) -> Self {
+        self.wasm = Some(WasmHook {
+            contract: contract.into(),
+            msg,
+        });
+        self
+    }
+
+    /// Return instruction read by the external side on the destination chain
+    pub fn return_hook(mut self, hook: ReturnHook) -> Self {
+        self.return_hook = Some(hook);
+        self
+    }
+
+    /// Hops the packet is forwarded through, in order
+    pub fn forward(mut self, hops: impl IntoIterator<Item = ForwardHop>) -> Self {
+        self.forward.extend(hops);
+        self
+    }
+
+    pub fn build(self) -> Result<String, ContractError> {
+        // the memo read by the chain the packet finally lands on
+        let mut next = Map::new();
+        if let Some(wasm) = self.wasm {
+            next.insert("wasm".to_string(), wasm.into_value());
+        }
+        if let Some(hook) = self.return_hook {
+            let mut return_hook = Map::new();
+            return_hook.insert("correlation_id".to_string(), Value::from(hook.correlation_id));
+            return_hook.insert("wasm".to_string(), hook.wasm.into_value());
+            next.insert("return_hook".to_string(), Value::Object(return_hook));
+        }
+        for hop in self.forward.into_iter().rev() {
+            let mut forward = Map::new();
+            forward.insert("receiver".to_string(), Value::String(hop.receiver));
+            forward.insert("port".to_string(), Value::String(hop.port));
+            forward.insert("channel".to_string(), Value::String(hop.channel));
+            if let Some(timeout) = hop.timeout {
+                forward.insert("timeout".to_string(), Value::String(timeout));
+            }
+            if let Some(retries) = hop.retries {
+                forward.insert("retries".to_string(), Value::from(retries));
+            }
+            if !next.is_empty() {
+                forward.insert("next".to_string(), Value::Object(next));
+            }
+            next = Map::new();
+            next.insert("forward".to_string(), Value::Object(forward));
+        }
+        if let Some(contract) = self.ibc_callback {
+            next.insert("ibc_callback".to_string(), Value::String(contract));
+        }
+
+        let memo = serde_json_wasm::to_string(&Value::Object(next))?;
+        if memo.len() > MAX_MEMO_LENGTH {
+            return Err(ContractError::MemoTooLong {
+                length: memo.len(),
+                max: MAX_MEMO_LENGTH,
+            });
+        }
+        Ok(memo)
+    }
+}
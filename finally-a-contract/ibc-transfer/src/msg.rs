@@ -1,9 +1,16 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Coin;
+use cosmwasm_std::{Addr, Binary, Coin, Uint128};
+use cw20::Cw20ReceiveMsg;
+
+use crate::state::ibc::{CompletedTransfer, IBCTransfer, PacketLifecycleStatus, TransferCallback};
+use crate::state::{AccessControl, PriceFeedConfig, Quote, RouteConfig, RouteStats};
 
 #[cw_serde]
 pub struct InstantiateMsg {}
 
+#[cw_serde]
+pub struct MigrateMsg {}
+
 #[cw_serde]
 pub enum ExecuteMsg {
     SendToken {
@@ -11,21 +18,278 @@ pub enum ExecuteMsg {
         channel: String,
         recipient: String,
         amount: Coin,
+        // contract executed with CallbackMsg::IbcTransferOutcome once the transfer is final
+        callback: Option<TransferCallback>,
     },
+    // the packet is given either by (channel, sequence) or by the correlation id of its return hook
     ReceiveToken {
-        channel: String, // channel id of the initial transfer
-        sequence: u64, // sequence number of the packet
+        channel: Option<String>, // channel id of the initial transfer
+        sequence: Option<u64>, // sequence number of the packet
+        correlation_id: Option<u64>,
+    },
+    // executed by ibc-hooks when the external address pays back. Without a correlation id the
+    // oldest acked packet of the payer in the sent denom is settled
+    ReceiveHookPayout { correlation_id: Option<u64> },
+    // CW20 tokens sent with a Cw20HookMsg, for routes that accept the CW20
+    Receive(Cw20ReceiveMsg),
+    // the sender cancels a quote, or a packet that wasn't acked yet so that it is refunded as
+    // soon as its funds come back. The packet is given like for ReceiveToken
+    CancelTransfer {
+        channel: Option<String>,
+        sequence: Option<u64>,
+        correlation_id: Option<u64>,
+        quote_id: Option<u64>,
     },
     // this is to recover tokens that were sent to the contract but never received
     RecoverToken{ },
     AddExternalAddress { alias: String, address: String },
     AddPort { alias: String, port: String },
-    AddChannel { alias: String, channel_id: u32 }
+    AddChannel { alias: String, channel_id: u32 },
+    // owner sets the forwarding path and other settings of a recipient alias
+    SetRoute { alias: String, config: RouteConfig },
+    UpdateConfig {
+        // seconds the external address has to pay back after a successful ack
+        response_deadline: Option<u64>,
+    },
+    // owner sets the price feed used by USD route limits and price feed payout rates, None removes it
+    SetPriceFeed { price_feed: Option<PriceFeedConfig> },
+    // owner marks a channel closed by the counterparty and turns a page of its packets into recoveries
+    CloseChannel { channel_id: String, limit: Option<u32> },
+    // owner undoes a CloseChannel of a channel that is still open, packets already failed over stay archived
+    ReopenChannel { channel_id: String },
+    // owner settles a Sent packet whose callback was an anomaly, as acked or as a refunding timeout
+    ResolveAnomaly { channel_id: String, sequence: u64, acked: bool },
+    // turn AwaitingResponse packets past their response deadline into recoveries
+    SweepExpired { limit: Option<u32> },
+    // owner funds the insurance pool with the attached funds
+    DepositInsurance {},
+    // owner takes funds out of the insurance pool
+    WithdrawInsurance { amount: Coin },
+    // owner moves contract funds that are not owed to anyone
+    SweepSurplus { denom: String },
+    // owner sets the address that may manage the sender lists, None removes it
+    SetAccessManager { manager: Option<String> },
+    // owner or access manager edits the SendToken allowlist and turns it on or off
+    UpdateAllowlist {
+        enabled: Option<bool>,
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    // owner or access manager edits the SendToken denylist
+    UpdateDenylist { add: Vec<String>, remove: Vec<String> },
+    // the counterparty of a two-phase route accepts a quote and its transfer is sent
+    AcceptQuote { quote_id: u64 },
+    // the quote sender or the counterparty cancels a quote, anyone once it expired. The funds are refunded
+    CancelQuote { quote_id: u64 },
+    // refund quotes that expired without being accepted
+    RefundExpiredQuotes { limit: Option<u32> },
+}
+
+/// Message embedded in the Cw20ReceiveMsg of a CW20 send to this contract
+#[cw_serde]
+pub enum Cw20HookMsg {
+    SendToken {
+        port: String,
+        channel: String,
+        recipient: String,
+        callback: Option<TransferCallback>,
+    },
 }
 
 #[cw_serde]
 #[derive(QueryResponses)]
-pub enum QueryMsg {}
+pub enum QueryMsg {
+    #[returns(RouteConfig)]
+    Route { alias: String },
+    #[returns(InsurancePoolResponse)]
+    InsurancePool { denom: String },
+    #[returns(ReconcileResponse)]
+    Reconcile { denom: String },
+    #[returns(CompletedTransfer)]
+    CompletedTransfer { channel: String, sequence: u64 },
+    #[returns(CompletedTransfersResponse)]
+    CompletedTransfersByUser {
+        address: String,
+        // (channel, sequence) of the last transfer of the previous page
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    #[returns(CompletedTransfersResponse)]
+    CompletedTransfersByRoute {
+        route: String,
+        // (channel, sequence) of the last transfer of the previous page
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+    #[returns(AccessControl)]
+    AccessControl {},
+    #[returns(AddressesResponse)]
+    Allowlist { start_after: Option<String>, limit: Option<u32> },
+    #[returns(AddressesResponse)]
+    Denylist { start_after: Option<String>, limit: Option<u32> },
+    #[returns(SenderAccessResponse)]
+    SenderAccess { address: String },
+    #[returns(Quote)]
+    Quote { quote_id: u64 },
+    #[returns(RouteStatsResponse)]
+    RouteStats {
+        route: String,
+        // denom of the last stats of the previous page
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    #[returns(ExposureResponse)]
+    Exposure {
+        route: String,
+        // return denom the route pays back in
+        denom: String,
+    },
+    #[returns(UserPositionsResponse)]
+    UserPositions {
+        address: String,
+        // (channel, sequence) of the last position of the previous page
+        start_after: Option<(String, u64)>,
+        limit: Option<u32>,
+    },
+}
+
+/// Message the callback contract of a SendToken is executed with. Contracts depending on
+/// this crate (with the `library` feature) handle it by adding an
+/// `IbcTransferOutcome(ibc_transfer::msg::IbcTransferOutcome)` and an
+/// `IbcTransferQuoteRefund(ibc_transfer::msg::QuoteRefund)` variant to their ExecuteMsg.
+/// Anyone can execute these variants, so the handler must check that `info.sender` is this
+/// contract before acting on them.
+#[cw_serde]
+pub enum CallbackMsg {
+    IbcTransferOutcome(IbcTransferOutcome),
+    IbcTransferQuoteRefund(QuoteRefund),
+}
+
+/// A packet reached a terminal status: it was paid out or turned into a recovery
+#[cw_serde]
+pub struct IbcTransferOutcome {
+    pub channel: String,
+    pub sequence: u64,
+    /// Sender of the SendToken
+    pub sender: Addr,
+    pub status: PacketLifecycleStatus,
+    /// What the sender was paid, or can claim with RecoverToken after a failed ack, a timeout
+    /// or a channel close. None when nothing was paid: acked packets of closed channels, and
+    /// failed payouts, which are left to claim with RecoverToken.
+    pub payout: Option<Coin>,
+    /// The `msg` of the callback
+    pub msg: Binary,
+}
+
+/// A quote was cancelled, or refunded after it expired, before its packet was sent
+#[cw_serde]
+pub struct QuoteRefund {
+    pub quote_id: u64,
+    /// Sender of the SendToken
+    pub sender: Addr,
+    /// The funds of the quote sent back to the sender
+    pub refund: Coin,
+    /// The `msg` of the callback
+    pub msg: Binary,
+}
+
+/// Data of a ReceiveHookPayout response, the packet that was paid out
+#[cw_serde]
+pub struct SettledPacket {
+    pub channel: String,
+    pub sequence: u64,
+}
+
+#[cw_serde]
+pub struct CompletedTransfersResponse {
+    pub transfers: Vec<CompletedTransfer>,
+}
+
+#[cw_serde]
+pub struct DenomStats {
+    pub denom: String,
+    pub stats: RouteStats,
+}
+
+/// Statistics of a route per denom, ordered by denom
+#[cw_serde]
+pub struct RouteStatsResponse {
+    pub route: String,
+    pub denoms: Vec<DenomStats>,
+}
+
+/// Obligation of the external address of a route in a return denom against its cap
+#[cw_serde]
+pub struct ExposureResponse {
+    pub route: String,
+    pub denom: String,
+    /// 2x obligation, in the return denom, of the packets awaiting a response or their ack
+    pub exposure: Uint128,
+    /// None when the route has no exposure cap in this denom
+    pub cap: Option<Uint128>,
+    /// Obligation left under the cap
+    pub headroom: Option<Uint128>,
+    /// Largest amount SendToken still accepts, the headroom over the payout multiplier.
+    /// None on routes with a payout rate, where it depends on the rate of the send.
+    pub max_send: Option<Uint128>,
+}
+
+/// An in-flight packet or a recovery of a user
+#[cw_serde]
+pub struct UserPosition {
+    pub transfer: IBCTransfer,
+    /// Whether the position is in the recoveries of the user rather than in flight
+    pub recovery: bool,
+    /// What the user is paid if the position settles: the payout of an in-flight packet or
+    /// the funds of a recovery. None for recoveries kept as a record.
+    pub expected_payout: Option<Coin>,
+}
+
+/// Positions of a user ordered by (channel, sequence)
+#[cw_serde]
+pub struct UserPositionsResponse {
+    pub positions: Vec<UserPosition>,
+}
+
+#[cw_serde]
+pub struct AddressesResponse {
+    pub addresses: Vec<Addr>,
+}
+
+/// Whether an address may call SendToken, and the lists it is on
+#[cw_serde]
+pub struct SenderAccessResponse {
+    pub allowed: bool,
+    pub allowlisted: bool,
+    pub denylisted: bool,
+}
+
+#[cw_serde]
+pub struct InsurancePoolResponse {
+    pub denom: String,
+    pub balance: Uint128,
+}
+
+/// Contract bank balance of a denom compared with what the contract owes
+#[cw_serde]
+pub struct ReconcileResponse {
+    pub denom: String,
+    pub balance: Uint128,
+    pub pending_recoveries: Uint128,
+    pub insurance_pool: Uint128,
+    /// Sent over IBC, not part of the balance unless escrowed
+    pub in_flight: Uint128,
+    /// Sent over native IBC channels and held in escrow
+    pub escrowed: Uint128,
+    /// Held in quotes waiting to be accepted
+    pub quoted: Uint128,
+    /// pending_recoveries + insurance_pool + escrowed + quoted
+    pub obligations: Uint128,
+    /// Balance not owed to anyone
+    pub surplus: Uint128,
+    /// Obligations the balance can't cover
+    pub shortfall: Uint128,
+}
 
 #[cw_serde]
 pub enum IBCLifecycleComplete {
@@ -0,0 +1,252 @@
+//! Native IBC entry points for chains without ibc-hooks (`native-ibc` feature).
+//!
+//! The contract runs an ICS-20 compatible channel on its own `wasm.<address>` port. Routes
+//! whose port resolves to that port send `FungibleTokenPacketData` packets directly and keep
+//! the tokens in escrow like the transfer module does. Acks and timeouts arrive here instead
+//! of through the ibc-hooks sudo callback and go through the same INFLIGHT_PACKETS and
+//! recovery handling. Vouchers the counterparty returns over the channel are unescrowed to
+//! their receiver, any other incoming packet gets an error ack.
+
+use cosmwasm_schema::cw_serde;
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    from_json, to_json_binary, BankMsg, Binary, Coin, DepsMut, Env, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
+    IbcChannelConnectMsg, IbcChannelOpenMsg, IbcChannelOpenResponse, IbcMsg, IbcOrder, IbcPacket, IbcPacketAckMsg,
+    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, IbcTimeout, Reply, Response, Storage, SubMsg,
+    SubMsgResult, Uint128,
+};
+
+use crate::consts::MsgReplyID;
+use crate::execute::{ibc_channel_id, track_sent_packet};
+use crate::ibc_lifecycle::{fail_over_channel, packet_hash, receive_packet_ack, receive_packet_timeout, CallbackSource};
+use crate::denom::{denom_trace, trace_denom};
+use crate::ledger;
+use crate::state::ibc::IBCTransfer;
+use crate::state::{
+    NativeChannel, TransferMsgReplyState, CLOSED_CHANNELS, INFLIGHT_PACKETS, NATIVE_CHANNELS, VOUCHER_RETURN_REPLY_STATE,
+};
+use crate::ContractError;
+
+pub const ICS20_VERSION: &str = "ics20-1";
+
+// Seconds before a packet sent on a native channel times out
+pub const PACKET_TIMEOUT: u64 = 10 * 60;
+
+/// ICS-20 packet data
+#[cw_serde]
+pub struct FungibleTokenPacketData {
+    pub denom: String,
+    pub amount: Uint128,
+    pub sender: String,
+    pub receiver: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub memo: String,
+}
+
+/// ICS-20 acknowledgement
+#[cw_serde]
+pub enum Ics20Ack {
+    Result(Binary),
+    Error(String),
+}
+
+/// Port the runtime binds to this contract
+pub fn contract_port(env: &Env) -> String {
+    format!("wasm.{}", env.contract.address)
+}
+
+/// Sends the packet of `state` on a channel of the contract port and tracks it as in-flight.
+/// CHANNELS stores channel numbers, the packet goes out on `channel-<number>`. The packet
+/// data carries the full trace of the denom so that `ibc/` vouchers keep their origin.
+pub fn send_packet(deps: DepsMut, env: Env, mut state: TransferMsgReplyState) -> Result<Response, ContractError> {
+    state.channel_id = ibc_channel_id(&state.channel_id);
+    if CLOSED_CHANNELS.has(deps.storage, &state.channel_id) {
+        return Err(ContractError::ChannelClosed {
+            channel_id: state.channel_id,
+        });
+    }
+    let mut channel = NATIVE_CHANNELS
+        .may_load(deps.storage, &state.channel_id)?
+        .ok_or_else(|| ContractError::UnknownNativeChannel {
+            channel_id: state.channel_id.clone(),
+        })?;
+    let sequence = channel.next_sequence;
+    channel.next_sequence += 1;
+    NATIVE_CHANNELS.save(deps.storage, &state.channel_id, &channel)?;
+
+    let data = FungibleTokenPacketData {
+        denom: denom_trace(&deps.querier, &state.denom)?,
+        amount: Uint128::new(state.amount),
+        sender: env.contract.address.to_string(),
+        receiver: state.to_address.clone(),
+        memo: state.memo.clone(),
+    };
+    let msg = IbcMsg::SendPacket {
+        channel_id: state.channel_id.clone(),
+        data: to_json_binary(&data)?,
+        timeout: IbcTimeout::with_timestamp(env.block.time.plus_seconds(PACKET_TIMEOUT)),
+    };
+    ledger::add_escrowed(deps.storage, &state.denom, state.amount)?;
+    Ok(track_sent_packet(deps, &env, state, sequence)?.add_message(msg))
+}
+
+fn validate_channel(channel: &IbcChannel, counterparty_version: Option<&str>) -> Result<(), ContractError> {
+    if channel.order != IbcOrder::Unordered {
+        return Err(ContractError::InvalidChannelOrder {});
+    }
+    for version in [Some(channel.version.as_str()), counterparty_version].into_iter().flatten() {
+        if version != ICS20_VERSION {
+            return Err(ContractError::InvalidChannelVersion {
+                version: version.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_open(_deps: DepsMut, _env: Env, msg: IbcChannelOpenMsg) -> Result<IbcChannelOpenResponse, ContractError> {
+    validate_channel(msg.channel(), msg.counterparty_version())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_connect(deps: DepsMut, _env: Env, msg: IbcChannelConnectMsg) -> Result<IbcBasicResponse, ContractError> {
+    let channel = msg.channel();
+    validate_channel(channel, msg.counterparty_version())?;
+    NATIVE_CHANNELS.save(
+        deps.storage,
+        &channel.endpoint.channel_id,
+        &NativeChannel {
+            counterparty: channel.counterparty_endpoint.clone(),
+            connection_id: channel.connection_id.clone(),
+            next_sequence: 1,
+        },
+    )?;
+    Ok(IbcBasicResponse::new()
+        .add_attribute("action", "ibc_channel_connect")
+        .add_attribute("channel", &channel.endpoint.channel_id))
+}
+
+/// Fails over the first page of the packets of the channel, the owner pages through
+/// the rest with CloseChannel. The channel stays in NATIVE_CHANNELS to release the escrow.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_channel_close(deps: DepsMut, env: Env, msg: IbcChannelCloseMsg) -> Result<IbcBasicResponse, ContractError> {
+    let channel_id = &msg.channel().endpoint.channel_id;
+    let res = fail_over_channel(deps, &env, channel_id, None)?;
+    Ok(basic_response(res).add_attribute("method", "ibc_channel_close"))
+}
+
+/// Unescrows the tokens of vouchers the counterparty sends back. Anything else, or a return
+/// that doesn't decode or exceeds the escrow, gets an error ack and is refunded by the
+/// sending chain. So does a return whose bank send fails, see `handle_voucher_return_reply`.
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_receive(deps: DepsMut, _env: Env, msg: IbcPacketReceiveMsg) -> Result<IbcReceiveResponse, ContractError> {
+    let res = IbcReceiveResponse::new().add_attribute("action", "ibc_packet_receive");
+    match receive_voucher(deps, &msg.packet) {
+        Ok(msg) => Ok(res
+            .set_ack(to_json_binary(&Ics20Ack::Result(Binary::from(vec![1])))?)
+            .add_submessage(SubMsg::reply_on_error(msg, MsgReplyID::VoucherReturn.repr()))),
+        Err(err) => Ok(res
+            .set_ack(to_json_binary(&Ics20Ack::Error(err.to_string()))?)
+            .add_attribute("error", err.to_string())),
+    }
+}
+
+/// The unescrow of a returned voucher failed (e.g. a blocked receiver). Its tokens go back
+/// into escrow and the data of the reply replaces the ack with an error ack, so that the
+/// sending chain refunds the sender.
+pub fn handle_voucher_return_reply(deps: DepsMut, reply: Reply) -> Result<Response, ContractError> {
+    let SubMsgResult::Err(err) = reply.result else {
+        return Ok(Response::new());
+    };
+    let returned = VOUCHER_RETURN_REPLY_STATE.load(deps.storage)?;
+    VOUCHER_RETURN_REPLY_STATE.remove(deps.storage);
+    ledger::add_escrowed(deps.storage, &returned.denom, returned.amount.u128())?;
+    Ok(Response::new()
+        .set_data(to_json_binary(&Ics20Ack::Error(err.clone()))?)
+        .add_attribute("action", "voucher_return_failed")
+        .add_attribute("error", err))
+}
+
+/// Takes the returned tokens out of escrow. A returning voucher carries the trace this
+/// contract sent prefixed with the counterparty end of the channel.
+fn receive_voucher(deps: DepsMut, packet: &IbcPacket) -> Result<BankMsg, ContractError> {
+    let data: FungibleTokenPacketData = from_json(&packet.data)?;
+    let prefix = format!("{}/{}/", packet.src.port_id, packet.src.channel_id);
+    let trace = data.denom.strip_prefix(&prefix).ok_or_else(|| ContractError::NotReturningVoucher {
+        denom: data.denom.clone(),
+    })?;
+    let denom = trace_denom(trace);
+    let receiver = deps.api.addr_validate(&data.receiver)?;
+    ledger::sub_escrowed(deps.storage, &denom, data.amount.u128())?;
+    let returned = Coin::new(data.amount.u128(), denom);
+    VOUCHER_RETURN_REPLY_STATE.save(deps.storage, &returned)?;
+    Ok(BankMsg::Send {
+        to_address: receiver.to_string(),
+        amount: vec![returned],
+    })
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_ack(mut deps: DepsMut, env: Env, msg: IbcPacketAckMsg) -> Result<IbcBasicResponse, ContractError> {
+    let ack = msg.acknowledgement.data;
+    let packet = msg.original_packet;
+    let success = matches!(from_json(&ack), Ok(Ics20Ack::Result(_)));
+    let sent = INFLIGHT_PACKETS.may_load(deps.storage, (&packet.src.channel_id, packet.sequence))?;
+    let res = receive_packet_ack(
+        deps.branch(),
+        env,
+        packet.src.channel_id.clone(),
+        packet.sequence,
+        String::from_utf8_lossy(ack.as_slice()).to_string(),
+        success,
+        CallbackSource::Native(&delivered_hash(&packet)),
+    )?;
+    release_escrow(deps.storage, &packet, sent)?;
+    Ok(basic_response(res))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn ibc_packet_timeout(mut deps: DepsMut, env: Env, msg: IbcPacketTimeoutMsg) -> Result<IbcBasicResponse, ContractError> {
+    let packet = msg.packet;
+    let sent = INFLIGHT_PACKETS.may_load(deps.storage, (&packet.src.channel_id, packet.sequence))?;
+    let res = receive_packet_timeout(
+        deps.branch(),
+        env,
+        packet.src.channel_id.clone(),
+        packet.sequence,
+        CallbackSource::Native(&delivered_hash(&packet)),
+    )?;
+    release_escrow(deps.storage, &packet, sent)?;
+    Ok(basic_response(res))
+}
+
+/// packet_hash of the ICS-20 data of `packet`, over the local denom of its trace like the
+/// hash stored on send. Data that doesn't decode matches nothing.
+fn delivered_hash(packet: &IbcPacket) -> String {
+    match from_json::<FungibleTokenPacketData>(&packet.data) {
+        Ok(data) => packet_hash(&trace_denom(&data.denom), data.amount.u128(), &data.receiver, &data.memo),
+        Err(_) => String::new(),
+    }
+}
+
+/// The tokens of a `sent` packet that became a recovery are no longer escrowed, they back
+/// the recovery. Packets still in flight were acked or their callback was not acted on.
+fn release_escrow(storage: &mut dyn Storage, packet: &IbcPacket, sent: Option<IBCTransfer>) -> Result<(), ContractError> {
+    let Some(sent) = sent else {
+        return Ok(());
+    };
+    if INFLIGHT_PACKETS.has(storage, (&packet.src.channel_id, packet.sequence)) {
+        return Ok(());
+    }
+    ledger::sub_escrowed(storage, &sent.denom, sent.amount)?;
+    Ok(())
+}
+
+fn basic_response(res: Response) -> IbcBasicResponse {
+    IbcBasicResponse::new()
+        .add_submessages(res.messages)
+        .add_attributes(res.attributes)
+        .add_events(res.events)
+}
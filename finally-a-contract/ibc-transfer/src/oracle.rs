@@ -0,0 +1,135 @@
+use cosmwasm_schema::{cw_serde, QueryResponses};
+use cosmwasm_std::{Addr, Coin, Decimal, Deps, Env, Timestamp};
+
+use crate::state::{PriceFeedConfig, RateSource, CONFIG};
+use crate::ContractError;
+
+/// Query the configured price feed contract has to answer
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum PriceFeedQueryMsg {
+    /// USD price of one unit of `denom`
+    #[returns(PriceResponse)]
+    Price { denom: String },
+}
+
+#[cw_serde]
+pub struct PriceResponse {
+    pub price: Decimal,
+    /// When the feed last updated the price
+    pub updated_at: Timestamp,
+}
+
+/// Source of USD prices
+pub trait PriceOracle {
+    fn price(&self, deps: Deps, denom: &str) -> Result<PriceResponse, ContractError>;
+}
+
+/// Prices read from a price feed contract with WasmQuery::Smart
+pub struct PriceFeed {
+    pub contract: Addr,
+}
+
+impl PriceOracle for PriceFeed {
+    fn price(&self, deps: Deps, denom: &str) -> Result<PriceResponse, ContractError> {
+        Ok(deps.querier.query_wasm_smart(
+            &self.contract,
+            &PriceFeedQueryMsg::Price {
+                denom: denom.to_string(),
+            },
+        )?)
+    }
+}
+
+/// Fixed prices, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockOracle {
+    pub prices: std::collections::BTreeMap<String, PriceResponse>,
+}
+
+#[cfg(test)]
+impl MockOracle {
+    pub fn with_price(mut self, denom: &str, price: Decimal, updated_at: Timestamp) -> Self {
+        self.prices.insert(denom.to_string(), PriceResponse { price, updated_at });
+        self
+    }
+}
+
+#[cfg(test)]
+impl PriceOracle for MockOracle {
+    fn price(&self, _deps: Deps, denom: &str) -> Result<PriceResponse, ContractError> {
+        self.prices
+            .get(denom)
+            .cloned()
+            .ok_or_else(|| cosmwasm_std::StdError::not_found(format!("price of {denom}")).into())
+    }
+}
+
+/// Prices of the configured price feed that are at most `max_age` seconds old
+pub struct FreshPrices<'a> {
+    pub oracle: &'a dyn PriceOracle,
+    pub max_age: u64,
+}
+
+impl<'a> FreshPrices<'a> {
+    /// USD price of one unit of `denom`, rejected when it is older than `max_age`
+    pub fn price(&self, deps: Deps, env: &Env, denom: &str) -> Result<Decimal, ContractError> {
+        let PriceResponse { price, updated_at } = self.oracle.price(deps, denom)?;
+        if updated_at.plus_seconds(self.max_age) < env.block.time {
+            return Err(ContractError::StalePrice {
+                denom: denom.to_string(),
+                updated_at,
+            });
+        }
+        Ok(price)
+    }
+
+    /// USD value of `coin`
+    pub fn value(&self, deps: Deps, env: &Env, coin: &Coin) -> Result<Decimal, ContractError> {
+        let price = self.price(deps, env, &coin.denom)?;
+        Ok(Decimal::from_atomics(coin.amount, 0)?.checked_mul(price)?)
+    }
+
+    /// Rejects sending `coin` when it is worth more than `limit` USD
+    pub fn check_limit(&self, deps: Deps, env: &Env, coin: &Coin, limit: Decimal) -> Result<(), ContractError> {
+        let value = self.value(deps, env, coin)?;
+        if value > limit {
+            return Err(ContractError::UsdLimitExceeded { value, limit });
+        }
+        Ok(())
+    }
+
+    /// Units of `quote` one unit of `base` is worth at their USD prices
+    pub fn rate(&self, deps: Deps, env: &Env, base: &str, quote: &str) -> Result<Decimal, ContractError> {
+        let base = self.price(deps, env, base)?;
+        let quote = self.price(deps, env, quote)?;
+        Ok(base.checked_div(quote)?)
+    }
+}
+
+/// The configured price feed contract
+pub fn price_feed(deps: Deps) -> Result<PriceFeedConfig, ContractError> {
+    CONFIG
+        .load(deps.storage)?
+        .price_feed
+        .ok_or(ContractError::NoPriceFeed {})
+}
+
+/// Rate of `quote` for one unit of `base` from `source`
+pub fn rate(deps: Deps, env: &Env, source: &RateSource, base: &str, quote: &str) -> Result<Decimal, ContractError> {
+    let rate = match source {
+        RateSource::Fixed(rate) => *rate,
+        RateSource::PriceFeed => {
+            let config = price_feed(deps)?;
+            let oracle = PriceFeed { contract: config.contract };
+            FreshPrices { oracle: &oracle, max_age: config.max_age }.rate(deps, env, base, quote)?
+        }
+    };
+    if rate.is_zero() {
+        return Err(ContractError::ZeroPayoutRate {
+            denom: quote.to_string(),
+        });
+    }
+    Ok(rate)
+}
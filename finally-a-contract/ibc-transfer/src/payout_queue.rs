@@ -0,0 +1,87 @@
+use bech32::FromBase32;
+use cosmwasm_std::{Addr, Order, StdError, StdResult, Storage};
+use sha2::{Digest, Sha256};
+
+use crate::consts::MAX_SWEEP_LIMIT;
+use crate::execute::ibc_channel_id;
+use crate::state::ibc::{IBCTransfer, PacketLifecycleStatus};
+use crate::state::{INFLIGHT_PACKETS, NEXT_PAYOUT_POSITION, PAYOUT_POSITIONS, PAYOUT_QUEUE};
+
+// Prefix ibc-hooks uses to derive the sender of the executes it makes
+const IBC_HOOKS_SENDER_PREFIX: &str = "ibc-wasm-hook-intermediary";
+
+/// Raw address ibc-hooks executes this contract as when `original_sender` transfers
+/// tokens to it over our `channel`: sha256(sha256(prefix) ++ "channel/original_sender")
+pub fn hook_sender_hash(channel: &str, original_sender: &str) -> Vec<u8> {
+    let prefix_hash = Sha256::digest(IBC_HOOKS_SENDER_PREFIX.as_bytes());
+    Sha256::new()
+        .chain_update(prefix_hash)
+        .chain_update(format!("{channel}/{original_sender}").as_bytes())
+        .finalize()
+        .to_vec()
+}
+
+/// Whether `sender` is the address ibc-hooks executes as for `original_sender` on `channel`
+pub fn is_hook_sender(sender: &Addr, channel: &str, original_sender: &str) -> bool {
+    bech32::decode(sender.as_str())
+        .ok()
+        .and_then(|(_, data, _)| Vec::<u8>::from_base32(&data).ok())
+        .is_some_and(|raw| raw == hook_sender_hash(channel, original_sender))
+}
+
+/// Raw address ibc-hooks executes as when the external address of `packet` pays it back
+fn payer(packet: &IBCTransfer) -> Vec<u8> {
+    hook_sender_hash(&ibc_channel_id(&packet.channel_id), &packet.receiver)
+}
+
+/// Queues an acked packet to be paid back by its external address through ibc-hooks.
+/// Packets are matched in the order they were acked.
+pub fn enqueue(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<()> {
+    let position = NEXT_PAYOUT_POSITION.may_load(storage)?.unwrap_or_default();
+    NEXT_PAYOUT_POSITION.save(storage, &(position + 1))?;
+    PAYOUT_POSITIONS.save(storage, (&packet.channel_id, packet.sequence), &position)?;
+    PAYOUT_QUEUE.save(
+        storage,
+        (&payer(packet), &packet.return_denom, position),
+        &(packet.channel_id.clone(), packet.sequence),
+    )
+}
+
+/// Takes a packet off the queue once it no longer awaits its response
+pub fn dequeue(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<()> {
+    let key = (packet.channel_id.as_str(), packet.sequence);
+    let Some(position) = PAYOUT_POSITIONS.may_load(storage, key)? else {
+        return Ok(());
+    };
+    PAYOUT_POSITIONS.remove(storage, key);
+    PAYOUT_QUEUE.remove(storage, (&payer(packet), &packet.return_denom, position));
+    Ok(())
+}
+
+/// Takes the oldest packet `payer` can still pay back in `denom` (its return denom) off the queue.
+///
+/// Settled packets are dequeued by the handler that settles them. Entries they left
+/// behind before that are pruned here, at most MAX_SWEEP_LIMIT per call.
+pub fn pop(storage: &mut dyn Storage, payer: &Addr, denom: &str) -> StdResult<Option<IBCTransfer>> {
+    // The queue is keyed by raw address so it doesn't depend on the chain prefix
+    let Ok((_, data, _)) = bech32::decode(payer.as_str()) else {
+        return Ok(None);
+    };
+    let payer = Vec::<u8>::from_base32(&data).map_err(|e| StdError::generic_err(e.to_string()))?;
+    let payer = payer.as_slice();
+    let entries = PAYOUT_QUEUE
+        .prefix((payer, denom))
+        .range(storage, None, None, Order::Ascending)
+        .take(MAX_SWEEP_LIMIT as usize)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (position, (channel, sequence)) in entries {
+        match INFLIGHT_PACKETS.may_load(storage, (&channel, sequence))? {
+            Some(packet) if packet.status == PacketLifecycleStatus::AwaitingResponse => {
+                dequeue(storage, &packet)?;
+                return Ok(Some(packet));
+            }
+            _ => PAYOUT_QUEUE.remove(storage, (payer, denom, position)),
+        }
+    }
+    Ok(None)
+}
@@ -52,3 +52,62 @@ pub struct MsgTransferResponse {
     #[prost(uint64, tag = "1")]
     pub sequence: u64,
 }
+
+// Response of the bank send paying out the external tokens. It has no fields.
+#[derive(Clone, PartialEq, Eq, ::prost::Message, serde::Serialize, serde::Deserialize)]
+pub struct MsgSendResponse {}
+
+// Denom trace of an `ibc/` voucher, to compute the voucher it returns as
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/ibc.applications.transfer.v1.QueryDenomTraceRequest")]
+#[proto_query(
+    path = "/ibc.applications.transfer.v1.Query/DenomTrace",
+    response_type = QueryDenomTraceResponse
+)]
+pub struct QueryDenomTraceRequest {
+    #[prost(string, tag = "1")]
+    pub hash: String,
+}
+
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/ibc.applications.transfer.v1.QueryDenomTraceResponse")]
+pub struct QueryDenomTraceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub denom_trace: Option<DenomTrace>,
+}
+
+#[derive(
+    Clone,
+    PartialEq,
+    Eq,
+    ::prost::Message,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    CosmwasmExt,
+)]
+#[proto_message(type_url = "/ibc.applications.transfer.v1.DenomTrace")]
+pub struct DenomTrace {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(string, tag = "2")]
+    pub base_denom: String,
+}
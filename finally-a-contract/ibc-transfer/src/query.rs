@@ -0,0 +1,232 @@
+use cosmwasm_std::{coin, Addr, Deps, Empty, Env, Order, StdError, StdResult, Uint128};
+use cw_storage_plus::{Bound, Map};
+
+use crate::consts::{DEFAULT_QUERY_LIMIT, MAX_QUERY_LIMIT, PAYOUT_MULTIPLIER};
+use crate::msg::{
+    AddressesResponse, CompletedTransfersResponse, DenomStats, ExposureResponse, RouteStatsResponse, SenderAccessResponse, InsurancePoolResponse, ReconcileResponse, UserPosition,
+    UserPositionsResponse,
+};
+use crate::state::ibc::CompletedTransfer;
+use crate::stats;
+use crate::state::{AccessControl, Quote, RouteConfig, ACCESS_CONTROL, INFLIGHT_PACKETS, QUOTES, RECOVERY_STATES, ROUTE_STATS, SENDER_ALLOWLIST, SENDER_DENYLIST, COMPLETED_TRANSFERS, INSURANCE_POOLS, LEDGERS, ROUTES};
+
+/// Settings of a route, the defaults if it was never set
+pub fn route(deps: Deps, alias: String) -> StdResult<RouteConfig> {
+    Ok(ROUTES.may_load(deps.storage, alias)?.unwrap_or_default())
+}
+
+/// Balance of the insurance pool for a denom
+pub fn insurance_pool(deps: Deps, denom: String) -> StdResult<InsurancePoolResponse> {
+    let balance = INSURANCE_POOLS.may_load(deps.storage, &denom)?.unwrap_or_default();
+    Ok(InsurancePoolResponse {
+        denom,
+        balance: Uint128::new(balance),
+    })
+}
+
+/// Compares the contract bank balance of a denom with the ledger
+pub fn reconcile(deps: Deps, env: Env, denom: String) -> StdResult<ReconcileResponse> {
+    let balance = deps
+        .querier
+        .query_balance(env.contract.address, denom.clone())?
+        .amount
+        .u128();
+    let ledger = LEDGERS.may_load(deps.storage, &denom)?.unwrap_or_default();
+    let insurance_pool = INSURANCE_POOLS.may_load(deps.storage, &denom)?.unwrap_or_default();
+    let obligations = [insurance_pool, ledger.escrowed, ledger.quoted]
+        .into_iter()
+        .try_fold(ledger.pending_recoveries, crate::ledger::checked_add)?;
+
+    Ok(ReconcileResponse {
+        denom,
+        balance: Uint128::new(balance),
+        pending_recoveries: Uint128::new(ledger.pending_recoveries),
+        insurance_pool: Uint128::new(insurance_pool),
+        in_flight: Uint128::new(ledger.in_flight),
+        escrowed: Uint128::new(ledger.escrowed),
+        quoted: Uint128::new(ledger.quoted),
+        obligations: Uint128::new(obligations),
+        surplus: Uint128::new(balance.saturating_sub(obligations)),
+        shortfall: Uint128::new(obligations.saturating_sub(balance)),
+    })
+}
+
+/// A completed transfer by (channel, sequence)
+pub fn completed_transfer(deps: Deps, channel: String, sequence: u64) -> StdResult<CompletedTransfer> {
+    COMPLETED_TRANSFERS.load(deps.storage, (&channel, sequence))
+}
+
+/// Completed transfers of a user, ordered by (channel, sequence)
+pub fn completed_transfers_by_user(
+    deps: Deps,
+    address: String,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<CompletedTransfersResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT) as usize;
+    let transfers = COMPLETED_TRANSFERS
+        .idx
+        .user
+        .prefix(address)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, transfer)| transfer))
+        .collect::<StdResult<_>>()?;
+    Ok(CompletedTransfersResponse { transfers })
+}
+
+/// Completed transfers sent over a route, ordered by (channel, sequence)
+pub fn completed_transfers_by_route(
+    deps: Deps,
+    route: String,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<CompletedTransfersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT) as usize;
+    let transfers = COMPLETED_TRANSFERS
+        .idx
+        .route
+        .prefix(route)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, transfer)| transfer))
+        .collect::<StdResult<_>>()?;
+    Ok(CompletedTransfersResponse { transfers })
+}
+
+/// Sender list settings and the access manager
+pub fn access_control(deps: Deps) -> StdResult<AccessControl> {
+    Ok(ACCESS_CONTROL.may_load(deps.storage)?.unwrap_or_default())
+}
+
+/// A page of the addresses of a sender list, in address order
+fn address_list(
+    deps: Deps,
+    list: &Map<&Addr, Empty>,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AddressesResponse> {
+    let start_after = start_after.map(|address| deps.api.addr_validate(&address)).transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT) as usize;
+    let addresses = list
+        .keys(deps.storage, start_after.as_ref().map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    Ok(AddressesResponse { addresses })
+}
+
+/// Senders on the allowlist
+pub fn allowlist(deps: Deps, start_after: Option<String>, limit: Option<u32>) -> StdResult<AddressesResponse> {
+    address_list(deps, &SENDER_ALLOWLIST, start_after, limit)
+}
+
+/// Senders on the denylist
+pub fn denylist(deps: Deps, start_after: Option<String>, limit: Option<u32>) -> StdResult<AddressesResponse> {
+    address_list(deps, &SENDER_DENYLIST, start_after, limit)
+}
+
+/// Whether `address` may call SendToken
+pub fn sender_access(deps: Deps, address: String) -> StdResult<SenderAccessResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let allowlisted = SENDER_ALLOWLIST.has(deps.storage, &address);
+    let denylisted = SENDER_DENYLIST.has(deps.storage, &address);
+    let allowlist_enabled = access_control(deps)?.allowlist_enabled;
+    Ok(SenderAccessResponse {
+        allowed: !denylisted && (allowlisted || !allowlist_enabled),
+        allowlisted,
+        denylisted,
+    })
+}
+
+/// A quote waiting to be accepted
+pub fn quote(deps: Deps, quote_id: u64) -> StdResult<Quote> {
+    QUOTES.load(deps.storage, quote_id)
+}
+
+/// Statistics of a route, per denom
+pub fn route_stats(
+    deps: Deps,
+    route: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<RouteStatsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT) as usize;
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let denoms = ROUTE_STATS
+        .prefix(&route)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(denom, stats)| DenomStats { denom, stats }))
+        .collect::<StdResult<_>>()?;
+    Ok(RouteStatsResponse { route, denoms })
+}
+
+/// Exposure of a route in the return denom `denom` and the headroom left under its cap
+pub fn exposure(deps: Deps, route: String, denom: String) -> StdResult<ExposureResponse> {
+    let exposure = stats::exposure(deps.storage, &route, &denom, Uint128::zero())?;
+    let config = ROUTES.may_load(deps.storage, route.clone())?.unwrap_or_default();
+    let cap = config.max_exposure.filter(|cap| cap.denom == denom).map(|cap| cap.amount);
+    let headroom = cap.map(|cap| cap.saturating_sub(exposure));
+    // without a payout rate the sent amount is the obligation over the multiplier
+    let max_send = headroom.filter(|_| config.payout.is_none()).map(|headroom| headroom / Uint128::new(PAYOUT_MULTIPLIER));
+    Ok(ExposureResponse {
+        route,
+        denom,
+        exposure,
+        cap,
+        headroom,
+        max_send,
+    })
+}
+
+/// In-flight packets and recoveries of a user with what they are expected to pay out
+pub fn user_positions(
+    deps: Deps,
+    address: String,
+    start_after: Option<(String, u64)>,
+    limit: Option<u32>,
+) -> StdResult<UserPositionsResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let limit = limit.unwrap_or(DEFAULT_QUERY_LIMIT).min(MAX_QUERY_LIMIT) as usize;
+    let mut positions = INFLIGHT_PACKETS
+        .idx
+        .user
+        .prefix(address.clone())
+        .range(deps.storage, start_after.clone().map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (_, transfer) = item?;
+            let payout = transfer.payout_amount().map_err(|e| StdError::generic_err(e.to_string()))?;
+            Ok(UserPosition {
+                expected_payout: Some(coin(payout, transfer.return_denom.clone())),
+                transfer,
+                recovery: false,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    // The recoveries of a user are kept together, merge the ones after the cursor
+    let recoveries = RECOVERY_STATES.may_load(deps.storage, &address)?.unwrap_or_default();
+    positions.extend(
+        recoveries
+            .into_iter()
+            .filter(|r| {
+                start_after
+                    .as_ref()
+                    .is_none_or(|(channel, sequence)| (&r.channel_id, r.sequence) > (channel, *sequence))
+            })
+            .map(|transfer| UserPosition {
+                expected_payout: transfer
+                    .is_funded_recovery()
+                    .then(|| coin(transfer.amount, transfer.denom.clone())),
+                transfer,
+                recovery: true,
+            }),
+    );
+    positions.sort_by(|a, b| {
+        (&a.transfer.channel_id, a.transfer.sequence).cmp(&(&b.transfer.channel_id, b.transfer.sequence))
+    });
+    positions.truncate(limit);
+    Ok(UserPositionsResponse { positions })
+}
@@ -0,0 +1,151 @@
+use cosmwasm_std::{
+    to_json_binary, Addr, Coin, CosmosMsg, DepsMut, Empty, Env, MessageInfo, Order, Response, Storage, SubMsg,
+};
+use cw_storage_plus::Bound;
+
+use crate::asset::payout_msg;
+use crate::callback;
+use crate::consts::{DEFAULT_SWEEP_LIMIT, MAX_SWEEP_LIMIT};
+use crate::execute::{channel_number, ibc_channel_id, recipient_address, send_transfer};
+use crate::ledger;
+use crate::payout_queue;
+use crate::state::{ibc, Quote, QuoteConfig, NEXT_QUOTE_ID, QUOTES, QUOTE_EXPIRIES, ROUTES};
+use crate::ContractError;
+
+/// Holds the funds of a SendToken on a two-phase route in a new quote. The quote id
+/// is the data of the response.
+#[allow(clippy::too_many_arguments)]
+pub fn create(
+    deps: DepsMut,
+    env: &Env,
+    config: &QuoteConfig,
+    sender: Addr,
+    amount: Coin,
+    asset: ibc::AssetKind,
+    port: String,
+    channel: String,
+    recipient: String,
+    callback: Option<ibc::TransferCallback>,
+) -> Result<Response, ContractError> {
+    let id = NEXT_QUOTE_ID.may_load(deps.storage)?.unwrap_or_default() + 1;
+    NEXT_QUOTE_ID.save(deps.storage, &id)?;
+    let quote = Quote {
+        id,
+        sender,
+        amount,
+        asset,
+        port,
+        channel,
+        recipient,
+        expires_at: env.block.time.plus_seconds(config.ttl),
+        callback,
+    };
+    QUOTES.save(deps.storage, id, &quote)?;
+    QUOTE_EXPIRIES.save(deps.storage, (quote.expires_at.seconds(), id), &Empty {})?;
+    ledger::add_quoted(deps.storage, &quote.amount.denom, quote.amount.amount.u128())?;
+
+    Ok(Response::new()
+        .set_data(to_json_binary(&id)?)
+        .add_attribute("action", "quote")
+        .add_attribute("quote_id", id.to_string())
+        .add_attribute("expires_at", quote.expires_at.seconds().to_string()))
+}
+
+/// Whether `addr` accepts quotes for the route of `quote`: its configured accepter, or
+/// the external address executing through ibc-hooks over the quote's channel
+fn is_counterparty(storage: &mut dyn Storage, quote: &Quote, addr: &Addr) -> Result<bool, ContractError> {
+    let (alias, external_address) = recipient_address(storage, quote.recipient.clone());
+    let accepter = ROUTES.may_load(storage, alias)?.and_then(|route| route.quote).and_then(|config| config.accepter);
+    if accepter.as_ref() == Some(addr) {
+        return Ok(true);
+    }
+    let (_, channel) = channel_number(storage, quote.channel.clone());
+    Ok(payout_queue::is_hook_sender(addr, &ibc_channel_id(&channel.to_string()), &external_address))
+}
+
+/// Removes a quote that was accepted or is refunded
+fn remove(storage: &mut dyn Storage, quote: &Quote) -> Result<(), ContractError> {
+    QUOTES.remove(storage, quote.id);
+    QUOTE_EXPIRIES.remove(storage, (quote.expires_at.seconds(), quote.id));
+    ledger::sub_quoted(storage, &quote.amount.denom, quote.amount.amount.u128())?;
+    Ok(())
+}
+
+/// Sends the funds of a quote back to its sender, with the refund message of its callback
+fn refund(storage: &mut dyn Storage, quote: Quote) -> Result<(CosmosMsg, Option<SubMsg>), ContractError> {
+    remove(storage, &quote)?;
+    let refund = callback::quote_refund_msg(&quote)?;
+    Ok((payout_msg(&quote.asset, &quote.sender, quote.amount)?, refund))
+}
+
+/// The counterparty accepts a quote before it expires and its transfer is sent
+pub fn accept(deps: DepsMut, env: Env, info: MessageInfo, quote_id: u64) -> Result<Response, ContractError> {
+    let quote = QUOTES.load(deps.storage, quote_id)?;
+    if !is_counterparty(deps.storage, &quote, &info.sender)? {
+        return Err(ContractError::Unauthorized {});
+    }
+    if env.block.time >= quote.expires_at {
+        return Err(ContractError::QuoteExpired { quote_id });
+    }
+    remove(deps.storage, &quote)?;
+
+    let res = send_transfer(
+        deps,
+        env,
+        quote.sender,
+        quote.amount,
+        quote.asset,
+        quote.port,
+        quote.channel,
+        quote.recipient,
+        quote.callback,
+    )?;
+    Ok(res
+        .add_attribute("action", "accept_quote")
+        .add_attribute("quote_id", quote_id.to_string()))
+}
+
+/// Refunds a quote. Its sender or counterparty can cancel it at any time, anyone once it expired.
+pub fn cancel(deps: DepsMut, env: Env, info: MessageInfo, quote_id: u64) -> Result<Response, ContractError> {
+    let quote = QUOTES.load(deps.storage, quote_id)?;
+    let allowed = env.block.time >= quote.expires_at
+        || info.sender == quote.sender
+        || is_counterparty(deps.storage, &quote, &info.sender)?;
+    if !allowed {
+        return Err(ContractError::Unauthorized {});
+    }
+    let (msg, outcome) = refund(deps.storage, quote)?;
+    Ok(Response::new()
+        .add_message(msg)
+        .add_submessages(outcome)
+        .add_attribute("action", "cancel_quote")
+        .add_attribute("quote_id", quote_id.to_string()))
+}
+
+/// Refunds quotes that expired without being accepted, oldest first
+pub fn refund_expired(deps: DepsMut, env: Env, limit: Option<u32>) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_SWEEP_LIMIT).min(MAX_SWEEP_LIMIT) as usize;
+    // A quote expires at the start of its expiry second
+    let expired = QUOTE_EXPIRIES
+        .keys(
+            deps.storage,
+            None,
+            Some(Bound::exclusive((env.block.time.seconds() + 1, 0))),
+            Order::Ascending,
+        )
+        .take(limit)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (mut msgs, mut outcomes) = (vec![], vec![]);
+    for (_, quote_id) in expired {
+        let quote = QUOTES.load(deps.storage, quote_id)?;
+        let (msg, outcome) = refund(deps.storage, quote)?;
+        msgs.push(msg);
+        outcomes.extend(outcome);
+    }
+    Ok(Response::new()
+        .add_attribute("action", "refund_expired_quotes")
+        .add_attribute("refunded", msgs.len().to_string())
+        .add_messages(msgs)
+        .add_submessages(outcomes))
+}
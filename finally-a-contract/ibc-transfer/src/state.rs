@@ -1,12 +1,32 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::Addr;
-use cw_storage_plus::{Map, Item};
+use cosmwasm_std::{Addr, Coin, Decimal, Empty, IbcEndpoint, Timestamp, Uint128};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
 
 use self::ibc::IBCTransfer;
+use crate::consts::DEFAULT_RESPONSE_DEADLINE;
+use crate::memo::ForwardHop;
 
 #[cw_serde]
 pub struct Config {
     pub owner: Addr,
+    /// Seconds the external address has to pay back once a packet is acked
+    #[serde(default = "default_response_deadline")]
+    pub response_deadline: u64,
+    /// USD prices used by route limits and price feed payout rates
+    #[serde(default)]
+    pub price_feed: Option<PriceFeedConfig>,
+}
+
+fn default_response_deadline() -> u64 {
+    DEFAULT_RESPONSE_DEADLINE
+}
+
+/// A price feed contract answering `oracle::PriceFeedQueryMsg`
+#[cw_serde]
+pub struct PriceFeedConfig {
+    pub contract: Addr,
+    /// Seconds after which a price is stale and sends depending on it are rejected
+    pub max_age: u64,
 }
 
 #[cw_serde]
@@ -16,11 +36,177 @@ pub struct TransferMsgReplyState {
     pub amount: u128,
     pub denom: String,
     pub sender: Addr,
+    pub return_denom: String,
+    pub asset: ibc::AssetKind,
+    pub route: ibc::RouteAliases,
+    pub memo: String,
+    pub correlation_id: Option<u64>,
+    pub payout_rate: Option<Decimal>,
+    pub callback: Option<ibc::TransferCallback>,
+}
+
+/// Factory denom balance of the contract before the adapter mints an unwrapped CW20
+#[cw_serde]
+pub struct UnwrapReplyState {
+    pub denom: String,
+    pub balance: Uint128,
+    pub amount: Uint128,
+}
+
+/// Context of the bank send paying the external tokens out to the user
+#[cw_serde]
+pub struct PayoutReplyState {
+    pub packet: IBCTransfer,
+    pub payout: Coin,
+}
+
+/// Per denom accounting of what the contract owes or has sent out
+#[cw_serde]
+#[derive(Default)]
+pub struct Ledger {
+    /// Funds held by the contract waiting to be recovered by users
+    pub pending_recoveries: u128,
+    /// Funds sent over IBC for packets still in INFLIGHT_PACKETS. These are not held by the contract.
+    pub in_flight: u128,
+    /// Funds sent over native IBC channels. Like the transfer module, the contract keeps them in escrow.
+    #[serde(default)]
+    pub escrowed: u128,
+    /// Funds of quotes waiting to be accepted, held by the contract
+    #[serde(default)]
+    pub quoted: u128,
+}
+
+/// Running totals of the packets of a route in one denom, in amounts of that denom
+#[cw_serde]
+#[derive(Default)]
+pub struct RouteStats {
+    pub sent: Uint128,
+    /// Sent amount of the packets waiting for their ack
+    pub pending: Uint128,
+    pub acked: Uint128,
+    pub timed_out: Uint128,
+    pub ack_failed: Uint128,
+    /// Sent amount of the packets whose payout reached the user
+    pub paid_out: Uint128,
+    /// Sent amount of the packets awaiting the response of the external address
+    pub outstanding: Uint128,
+    /// Recoveries paid out to users. Payout recoveries count in the denom they are paid in.
+    pub recovered: Uint128,
+}
+
+/// Settings of a route, keyed by the recipient (external address) alias
+#[cw_serde]
+#[derive(Default)]
+pub struct RouteConfig {
+    /// Packet-forward-middleware hops taken after the packet reaches the external address chain
+    pub forward_path: Vec<ForwardHop>,
+    /// Put a `return_hook` in the memo telling the external side how to call ReceiveToken
+    #[serde(default)]
+    pub return_hook: bool,
+    /// Form of the tokens the external address pays back with
+    #[serde(default)]
+    pub return_denom: ReturnDenom,
+    /// CW20 token accepted through the Receive hook on this route
+    #[serde(default)]
+    pub cw20: Option<Cw20Route>,
+    /// Denom and rate the external address pays back with instead of the return denom
+    #[serde(default)]
+    pub payout: Option<PayoutConfig>,
+    /// Largest USD value of a single send, priced with the configured price feed
+    #[serde(default)]
+    pub max_send_usd: Option<Decimal>,
+    /// Hold sends in quotes until the counterparty accepts them
+    #[serde(default)]
+    pub quote: Option<QuoteConfig>,
+    /// Largest 2x obligation the external address may owe, in the return denom it pays back
+    /// with, counting the packets awaiting its response and the ones waiting for their ack.
+    /// Sends paying back in another denom are rejected.
+    #[serde(default)]
+    pub max_exposure: Option<Coin>,
+}
+
+/// Two-phase sends: SendToken holds the funds in a quote and the MsgTransfer is only
+/// sent once the counterparty accepts it
+#[cw_serde]
+pub struct QuoteConfig {
+    /// Address that may accept quotes besides the ibc-hooks sender of the external address
+    pub accepter: Option<Addr>,
+    /// Seconds a quote can be accepted for, after that it is refunded
+    pub ttl: u64,
+}
+
+/// Pays a route back in another denom, e.g. uatom sent out and a stablecoin paid back
+#[cw_serde]
+pub struct PayoutConfig {
+    pub denom: String,
+    pub rate: RateSource,
+}
+
+/// Where the rate of a payout denom comes from, in units of the payout denom per unit sent.
+/// The rate is read when the packet is sent and kept with it.
+///
+/// Amounts are raw base units and the rate is applied to them as is, without normalising
+/// the decimals of the two denoms: a rate for a 6 decimals denom paid back in an 18 decimals
+/// one must include the 10^12 factor.
+#[cw_serde]
+pub enum RateSource {
+    /// Set by the owner
+    Fixed(Decimal),
+    /// Ratio of the USD prices of the sent and payout denoms from the configured price feed
+    PriceFeed,
+}
+
+/// Funds of a SendToken waiting for the counterparty to accept them. The port, channel
+/// and recipient are the SendToken aliases, resolved when the quote is accepted.
+#[cw_serde]
+pub struct Quote {
+    pub id: u64,
+    pub sender: Addr,
+    pub amount: Coin,
+    pub asset: ibc::AssetKind,
+    pub port: String,
+    pub channel: String,
+    pub recipient: String,
+    pub expires_at: Timestamp,
+    pub callback: Option<ibc::TransferCallback>,
+}
+
+/// A CW20 token and the token factory adapter it is unwrapped to a native denom with
+/// (`factory/{adapter}/{contract}`) before being sent over IBC
+#[cw_serde]
+pub struct Cw20Route {
+    pub contract: Addr,
+    pub adapter: Addr,
+}
+
+/// Denom the payout of a packet arrives in
+#[cw_serde]
+#[derive(Default)]
+pub enum ReturnDenom {
+    /// The denom that was sent, e.g. the vouchers sent out unwind back to it
+    #[default]
+    Native,
+    /// The ICS-20 voucher of the sent base denom received over the channel the packet was sent on
+    Voucher,
+}
+
+/// Who may send tokens and who may manage the sender lists besides the owner
+#[cw_serde]
+#[derive(Default)]
+pub struct AccessControl {
+    pub manager: Option<Addr>,
+    /// Only senders on the allowlist may send when set. The denylist always applies.
+    pub allowlist_enabled: bool,
 }
 
 pub mod ibc {
     use std::fmt;
 
+    use cosmwasm_std::{Binary, Env, Timestamp, Uint128};
+
+    use crate::consts::PAYOUT_MULTIPLIER;
+    use crate::ContractError;
+
     use super::*;
 
     #[cw_serde]
@@ -32,6 +218,10 @@ pub mod ibc {
         AwaitingResponse,
         SendingExternalTokens,
         SendingExternalTokensFailure,
+        ResponseExpired,
+        InsuredPayout,
+        PaidOut,
+        ChannelClosed,
     }
 
     impl fmt::Display for PacketLifecycleStatus {
@@ -44,10 +234,136 @@ pub mod ibc {
                 PacketLifecycleStatus::AwaitingResponse => write!(f, "AwaitingResponse"),
                 PacketLifecycleStatus::SendingExternalTokens => write!(f, "SendingExternalTokens"),
                 PacketLifecycleStatus::SendingExternalTokensFailure => write!(f, "SendingExternalTokensFailure"),
+                PacketLifecycleStatus::ResponseExpired => write!(f, "ResponseExpired"),
+                PacketLifecycleStatus::InsuredPayout => write!(f, "InsuredPayout"),
+                PacketLifecycleStatus::PaidOut => write!(f, "PaidOut"),
+                PacketLifecycleStatus::ChannelClosed => write!(f, "ChannelClosed"),
+            }
+        }
+    }
+
+    impl PacketLifecycleStatus {
+        /// Every status, in lifecycle order
+        pub const ALL: [PacketLifecycleStatus; 11] = [
+            PacketLifecycleStatus::Sent,
+            PacketLifecycleStatus::AckSuccess,
+            PacketLifecycleStatus::AckFailure,
+            PacketLifecycleStatus::TimedOut,
+            PacketLifecycleStatus::AwaitingResponse,
+            PacketLifecycleStatus::SendingExternalTokens,
+            PacketLifecycleStatus::SendingExternalTokensFailure,
+            PacketLifecycleStatus::ResponseExpired,
+            PacketLifecycleStatus::InsuredPayout,
+            PacketLifecycleStatus::PaidOut,
+            PacketLifecycleStatus::ChannelClosed,
+        ];
+
+        /// Whether a packet in this status may move to `next`.
+        ///
+        /// ```text
+        /// Sent -> AckSuccess -> AwaitingResponse -> SendingExternalTokens -> PaidOut
+        ///  |                         |                  ^  '-> SendingExternalTokensFailure
+        ///  |-> AckFailure            |-> ResponseExpired -'
+        ///  |-> TimedOut              |         '-> InsuredPayout
+        ///  '-> ChannelClosed         '-> ChannelClosed
+        /// ```
+        ///
+        /// An expired response is paid out when the external address pays back late.
+        pub fn can_transition_to(&self, next: &PacketLifecycleStatus) -> bool {
+            use PacketLifecycleStatus::*;
+            matches!(
+                (self, next),
+                (Sent, AckSuccess | AckFailure | TimedOut | ChannelClosed)
+                    | (AckSuccess, AwaitingResponse)
+                    | (AwaitingResponse, SendingExternalTokens | ResponseExpired | ChannelClosed)
+                    | (SendingExternalTokens, PaidOut | SendingExternalTokensFailure)
+                    | (ResponseExpired, SendingExternalTokens | InsuredPayout)
+            )
+        }
+
+        /// Whether the packet has left the lifecycle for good
+        pub fn is_terminal(&self) -> bool {
+            PacketLifecycleStatus::ALL.iter().all(|next| !self.can_transition_to(next))
+        }
+    }
+
+    /// Lifecycle callback the chain delivered for a packet
+    #[cw_serde]
+    pub enum LifecycleCallback {
+        Ack { success: bool },
+        Timeout,
+    }
+
+    impl fmt::Display for LifecycleCallback {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                LifecycleCallback::Ack { success: true } => write!(f, "ack_success"),
+                LifecycleCallback::Ack { success: false } => write!(f, "ack_failure"),
+                LifecycleCallback::Timeout => write!(f, "timeout"),
             }
         }
     }
 
+    /// Block at which a packet went through a lifecycle transition
+    #[cw_serde]
+    pub struct BlockStamp {
+        pub height: u64,
+        pub time: Timestamp,
+    }
+
+    impl BlockStamp {
+        pub fn now(env: &Env) -> Self {
+            BlockStamp {
+                height: env.block.height,
+                time: env.block.time,
+            }
+        }
+    }
+
+    /// When a packet was sent, acked, paid out to the user, failed (ack failure,
+    /// timeout or expired response), turned into a recovery and when that
+    /// recovery was claimed by its user
+    #[cw_serde]
+    #[derive(Default)]
+    pub struct Timeline {
+        pub sent: Option<BlockStamp>,
+        pub acked: Option<BlockStamp>,
+        pub paid: Option<BlockStamp>,
+        pub failed: Option<BlockStamp>,
+        pub recovered: Option<BlockStamp>,
+        #[serde(default)]
+        pub claimed: Option<BlockStamp>,
+    }
+
+    /// Asset a packet was funded with
+    #[cw_serde]
+    #[derive(Default)]
+    pub enum AssetKind {
+        /// Bank coins sent with SendToken
+        #[default]
+        Native,
+        /// CW20 tokens sent through the Receive hook, unwrapped to the factory denom of the adapter
+        Cw20 { contract: Addr, adapter: Addr },
+    }
+
+    /// Aliases a packet was sent with. The recipient alias identifies the route.
+    /// Empty on packets recorded before the aliases were stored.
+    #[cw_serde]
+    #[derive(Default)]
+    pub struct RouteAliases {
+        pub recipient: String,
+        pub port: String,
+        pub channel: String,
+    }
+
+    /// Contract a SendToken asks to execute with `msg::CallbackMsg::IbcTransferOutcome` once
+    /// its transfer reaches a terminal status. `msg` is passed back untouched.
+    #[cw_serde]
+    pub struct TransferCallback {
+        pub contract: Addr,
+        pub msg: Binary,
+    }
+
     /// A transfer packet sent by this contract that is expected to be received but
     /// needs to be tracked in case the receive fails or times-out
     #[cw_serde]
@@ -56,8 +372,101 @@ pub mod ibc {
         pub channel_id: String,
         pub sequence: u64,
         pub amount: u128,
+        /// Denom that was sent
         pub denom: String,
+        /// Denom the external address pays back in, the sent denom, its voucher or the route payout denom.
+        /// Missing on packets recorded before it was introduced, migrate sets it to `denom`.
+        #[serde(default)]
+        pub return_denom: String,
+        #[serde(default)]
+        pub asset: AssetKind,
         pub status: PacketLifecycleStatus,
+        /// Time after which the external address is no longer expected to pay back.
+        /// Set when the packet is successfully acked.
+        #[serde(default)]
+        pub response_deadline: Option<Timestamp>,
+        #[serde(default)]
+        pub route: RouteAliases,
+        /// Address on the remote chain the packet was sent to
+        #[serde(default)]
+        pub receiver: String,
+        #[serde(default)]
+        pub memo: String,
+        /// Id the external side can pay back with instead of (channel_id, sequence)
+        #[serde(default)]
+        pub correlation_id: Option<u64>,
+        /// Rate of the route payout denom the packet was sent with
+        #[serde(default)]
+        pub payout_rate: Option<Decimal>,
+        /// `ibc_lifecycle::packet_hash` of what was sent, checked against lifecycle callbacks.
+        /// Missing on packets recorded before it was introduced.
+        #[serde(default)]
+        pub packet_hash: Option<String>,
+        /// The sender cancelled the transfer, its funds are refunded as soon as they come back
+        #[serde(default)]
+        pub cancel_requested: bool,
+        /// The channel closed before the packet was acked. It stays in flight until the chain
+        /// delivers its timeout, which refunds it as a ChannelClosed recovery.
+        #[serde(default)]
+        pub channel_closed: bool,
+        /// Why the last callback for the packet was not acted on, until the owner resolves it
+        #[serde(default)]
+        pub anomaly: Option<String>,
+        /// Contract told about the outcome of the transfer once it is final
+        #[serde(default)]
+        pub callback: Option<TransferCallback>,
+        #[serde(default)]
+        pub timeline: Timeline,
+    }
+
+    impl IBCTransfer {
+        /// Moves the packet to `next`, rejecting transitions the lifecycle doesn't allow
+        pub fn transition(&mut self, next: PacketLifecycleStatus) -> Result<(), ContractError> {
+            if !self.status.can_transition_to(&next) {
+                return Err(ContractError::InvalidInflightPacketState {
+                    channel_id: self.channel_id.clone(),
+                    sequence: self.sequence,
+                    status: self.status.to_string(),
+                });
+            }
+            self.status = next;
+            Ok(())
+        }
+
+        /// What the external address pays back in `return_denom`
+        pub fn payout_amount(&self) -> Result<u128, ContractError> {
+            payout_amount(self.amount, self.payout_rate)
+        }
+
+        /// Whether the contract holds the funds backing this packet once it is a recovery.
+        /// An expired response, or a channel closing after the ack, leaves the tokens with
+        /// the external address, so the recovery is only a record of what the user is owed.
+        pub fn is_funded_recovery(&self) -> bool {
+            match self.status {
+                PacketLifecycleStatus::ResponseExpired => false,
+                PacketLifecycleStatus::ChannelClosed => self.timeline.acked.is_none(),
+                _ => true,
+            }
+        }
+    }
+
+    /// What the external address pays back for `amount` sent: the multiplied amount,
+    /// converted at the payout rate (rounded down) when there is one
+    pub fn payout_amount(amount: u128, payout_rate: Option<Decimal>) -> Result<u128, ContractError> {
+        let payout = Uint128::new(amount).checked_mul(Uint128::from(PAYOUT_MULTIPLIER))?;
+        Ok(match payout_rate {
+            Some(rate) => payout.checked_mul_floor(rate)?.u128(),
+            None => payout.u128(),
+        })
+    }
+
+    /// A transfer that was paid out or recovered and is no longer tracked
+    #[cw_serde]
+    pub struct CompletedTransfer {
+        /// The packet with its final status and timeline
+        pub transfer: IBCTransfer,
+        /// What was finally sent to the user
+        pub payout: Coin,
     }
 }
 
@@ -66,14 +475,119 @@ pub const CONFIG: Item<Config> = Item::new("config");
 pub const EXTERNAL_ADDRESSES: Map<String, String> = Map::new("recipient_address");
 pub const CHANNELS: Map<String, u32> = Map::new("channels");
 pub const PORTS: Map<String, String> = Map::new("ports");
+pub const ROUTES: Map<String, RouteConfig> = Map::new("routes");
+
+/// An open channel of the contract's own IBC port
+#[cw_serde]
+pub struct NativeChannel {
+    pub counterparty: IbcEndpoint,
+    pub connection_id: String,
+    /// Sequence the next packet sent on the channel gets. Only this contract sends on
+    /// the channel, so it follows the sequence the IBC module assigns.
+    pub next_sequence: u64,
+}
+
+/// Channels closed by the counterparty by channel id. Aliases of these channels can't be sent on.
+pub const CLOSED_CHANNELS: Map<&str, Empty> = Map::new("closed_channels");
+
+/// Channels of the contract's own port by channel id, see `native_ibc`
+pub const NATIVE_CHANNELS: Map<&str, NativeChannel> = Map::new("native_channels");
+
+pub const ACCESS_CONTROL: Item<AccessControl> = Item::new("access_control");
+pub const SENDER_ALLOWLIST: Map<&Addr, Empty> = Map::new("sender_allowlist");
+pub const SENDER_DENYLIST: Map<&Addr, Empty> = Map::new("sender_denylist");
+
+// Quotes of two-phase routes, and their ids ordered by expiry for refunds
+pub const NEXT_QUOTE_ID: Item<u64> = Item::new("next_quote_id");
+pub const QUOTES: Map<u64, Quote> = Map::new("quotes");
+pub const QUOTE_EXPIRIES: Map<(u64, u64), Empty> = Map::new("quote_expiries");
+
+// Correlation ids handed out in return hooks and the (channel_id, sequence) they were sent as
+pub const NEXT_CORRELATION_ID: Item<u64> = Item::new("next_correlation_id");
+pub const CORRELATION_IDS: Map<u64, (String, u64)> = Map::new("correlation_ids");
+
+// Acked packets waiting for an ibc-hooks payout, (payer, denom, position) -> (channel_id, sequence).
+// The payer is the raw address ibc-hooks executes the contract as for the external address.
+pub const NEXT_PAYOUT_POSITION: Item<u64> = Item::new("next_payout_position");
+pub const PAYOUT_QUEUE: Map<(&[u8], &str, u64), (String, u64)> = Map::new("payout_queue");
+// Position of a queued packet, (channel_id, sequence) -> position, to take it off the queue when settled
+pub const PAYOUT_POSITIONS: Map<(&str, u64), u64> = Map::new("payout_positions");
 
 // save context for ibc transfer reply
 pub const TRANSFER_REPLY_STATE: Item<TransferMsgReplyState> = Item::new("transfer_reply_state");
+// save context for the unescrow of returned vouchers. cw20-ics20 style, it is saved for
+// every return and only read (and cleared) when the send fails.
+pub const VOUCHER_RETURN_REPLY_STATE: Item<Coin> = Item::new("voucher_return_reply_state");
+// save context for the cw20 unwrap reply
+pub const CW20_UNWRAP_REPLY_STATE: Item<UnwrapReplyState> = Item::new("cw20_unwrap_reply_state");
 // save context for transferring external tokens to sender reply
-pub const SEND_EXTERNAL_TOKENS_REPLY_STATE: Item<IBCTransfer> = Item::new("send_external_tokens_reply_state");
+pub const SEND_EXTERNAL_TOKENS_REPLY_STATE: Item<PayoutReplyState> = Item::new("send_external_tokens_reply_state");
+
+pub struct InflightPacketIndexes<'a> {
+    pub user: MultiIndex<'a, Addr, ibc::IBCTransfer, (String, u64)>,
+}
+
+impl<'a> IndexList<ibc::IBCTransfer> for InflightPacketIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<ibc::IBCTransfer>> + '_> {
+        let v: Vec<&dyn Index<ibc::IBCTransfer>> = vec![&self.user];
+        Box::new(v.into_iter())
+    }
+}
+
+/// In-Flight packets by (source_channel_id, sequence), indexed by sender
+pub const INFLIGHT_PACKETS: IndexedMap<(&str, u64), ibc::IBCTransfer, InflightPacketIndexes> = IndexedMap::new(
+    "inflight",
+    InflightPacketIndexes {
+        user: MultiIndex::new(|_pk, p| p.recovery_addr.clone(), "inflight", "inflight__user"),
+    },
+);
+
+/// Lifecycle callback processed for each packet by (source_channel_id, sequence). A packet
+/// gets either an ack or a timeout, anything delivered after it is a replay.
+pub const PROCESSED_CALLBACKS: Map<(&str, u64), ibc::LifecycleCallback> = Map::new("processed_callbacks");
+
+/// AwaitingResponse packets by (response_deadline in seconds, source_channel_id, sequence)
+/// so that expired packets can be swept in deadline order.
+pub const RESPONSE_DEADLINES: Map<(u64, &str, u64), Empty> = Map::new("response_deadlines");
+
+/// Insurance pool balance by denom. Funded by the owner and kept apart from user funds.
+pub const INSURANCE_POOLS: Map<&str, u128> = Map::new("insurance_pools");
+
+/// Expired packets paid out from the insurance pool by (source_channel_id, sequence).
+/// A late payment from the external address for one of these reimburses the pool.
+pub const INSURED_PACKETS: Map<(&str, u64), ibc::IBCTransfer> = Map::new("insured_packets");
+
+/// Accounting ledger by denom. The insurance pool balance is tracked in INSURANCE_POOLS.
+pub const LEDGERS: Map<&str, Ledger> = Map::new("ledgers");
 
-/// In-Flight packets by (source_channel_id, sequence)
-pub const INFLIGHT_PACKETS: Map<(&str, u64), ibc::IBCTransfer> = Map::new("inflight");
+/// Statistics by (route alias, denom)
+pub const ROUTE_STATS: Map<(&str, &str), RouteStats> = Map::new("route_stats");
+
+/// What the external address of a route owes in a return denom for its packets waiting for
+/// their ack or its response, by (recipient alias, return denom)
+pub const ROUTE_EXPOSURE: Map<(&str, &str), Uint128> = Map::new("route_exposure");
 
 /// Recovery. This tracks any recovery that an addr can execute.
 pub const RECOVERY_STATES: Map<&Addr, Vec<ibc::IBCTransfer>> = Map::new("recovery");
+
+pub struct CompletedTransferIndexes<'a> {
+    pub user: MultiIndex<'a, Addr, ibc::CompletedTransfer, (String, u64)>,
+    pub route: MultiIndex<'a, String, ibc::CompletedTransfer, (String, u64)>,
+}
+
+impl<'a> IndexList<ibc::CompletedTransfer> for CompletedTransferIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<ibc::CompletedTransfer>> + '_> {
+        let v: Vec<&dyn Index<ibc::CompletedTransfer>> = vec![&self.user, &self.route];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Archive of completed transfers by (source_channel_id, sequence), indexed by user and route
+pub const COMPLETED_TRANSFERS: IndexedMap<(&str, u64), ibc::CompletedTransfer, CompletedTransferIndexes> =
+    IndexedMap::new(
+        "completed",
+        CompletedTransferIndexes {
+            user: MultiIndex::new(|_pk, c| c.transfer.recovery_addr.clone(), "completed", "completed__user"),
+            route: MultiIndex::new(|_pk, c| c.transfer.route.recipient.clone(), "completed", "completed__route"),
+        },
+    );
@@ -0,0 +1,121 @@
+use cosmwasm_std::{Order, StdError, StdResult, Storage, Uint128};
+
+use crate::state::ibc::{IBCTransfer, PacketLifecycleStatus};
+use crate::state::{RouteStats, INFLIGHT_PACKETS, ROUTE_EXPOSURE, ROUTE_STATS};
+
+/// Applies `action` to the stats of the route and denom of `packet` with its amount and saves them
+fn update<F>(storage: &mut dyn Storage, packet: &IBCTransfer, action: F) -> StdResult<RouteStats>
+where
+    F: FnOnce(&mut RouteStats, Uint128),
+{
+    let key = (packet.route.recipient.as_str(), packet.denom.as_str());
+    let mut stats = ROUTE_STATS.may_load(storage, key)?.unwrap_or_default();
+    action(&mut stats, Uint128::new(packet.amount));
+    ROUTE_STATS.save(storage, key, &stats)?;
+    Ok(stats)
+}
+
+/// Adds what the external address owes for `packet` to the exposure of its route in the
+/// return denom, or takes it out once the packet no longer exposes the route. Taking out
+/// saturates like the stats, migrate backfills the packets sent before the exposure existed.
+fn expose(storage: &mut dyn Storage, packet: &IBCTransfer, exposed: bool) -> StdResult<()> {
+    let key = (packet.route.recipient.as_str(), packet.return_denom.as_str());
+    let obligation = packet
+        .payout_amount()
+        .map_err(|err| StdError::generic_err(err.to_string()))?;
+    let exposure = ROUTE_EXPOSURE.may_load(storage, key)?.unwrap_or_default();
+    let exposure = match exposed {
+        true => exposure.checked_add(Uint128::new(obligation))?,
+        false => exposure.saturating_sub(Uint128::new(obligation)),
+    };
+    ROUTE_EXPOSURE.save(storage, key, &exposure)
+}
+
+/// A packet was sent over IBC
+pub fn sent(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    expose(storage, packet, true)?;
+    update(storage, packet, |s, amount| {
+        s.sent += amount;
+        s.pending += amount;
+    })
+}
+
+/// Saturating, packets sent before the stats existed were never counted as pending
+fn ack_received(stats: &mut RouteStats, amount: Uint128) {
+    stats.pending = stats.pending.saturating_sub(amount);
+}
+
+/// A packet was acked and now awaits the response of the external address
+pub fn acked(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    update(storage, packet, |s, amount| {
+        ack_received(s, amount);
+        s.acked += amount;
+        s.outstanding += amount;
+    })
+}
+
+pub fn ack_failed(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    expose(storage, packet, false)?;
+    update(storage, packet, |s, amount| {
+        ack_received(s, amount);
+        s.ack_failed += amount;
+    })
+}
+
+pub fn timed_out(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    expose(storage, packet, false)?;
+    update(storage, packet, |s, amount| {
+        ack_received(s, amount);
+        s.timed_out += amount;
+    })
+}
+
+/// A packet waiting for its ack when its channel closed timed out
+pub fn unacked_closed(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    expose(storage, packet, false)?;
+    update(storage, packet, ack_received)
+}
+
+/// A packet no longer awaits a response: it is being paid out, expired or its channel closed.
+/// Saturates at zero for packets acked before the stats existed.
+pub fn settled(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    expose(storage, packet, false)?;
+    update(storage, packet, |s, amount| s.outstanding = s.outstanding.saturating_sub(amount))
+}
+
+/// The payout of a packet reached the user
+pub fn paid_out(storage: &mut dyn Storage, packet: &IBCTransfer) -> StdResult<RouteStats> {
+    update(storage, packet, |s, amount| s.paid_out += amount)
+}
+
+/// A recovery was paid out to its user
+pub fn recovered(storage: &mut dyn Storage, recovery: &IBCTransfer) -> StdResult<RouteStats> {
+    update(storage, recovery, |s, amount| s.recovered += amount)
+}
+
+/// What the external address of `route` owes in `return_denom` for the packets waiting for
+/// their ack or its response, with an `extra` obligation on top
+pub fn exposure(storage: &dyn Storage, route: &str, return_denom: &str, extra: Uint128) -> StdResult<Uint128> {
+    let exposure = ROUTE_EXPOSURE.may_load(storage, (route, return_denom))?.unwrap_or_default();
+    Ok(exposure.checked_add(extra)?)
+}
+
+/// Recomputes the exposure of every route from the packets in flight. Run on migration so
+/// that packets sent before the exposure was tracked count against the caps.
+pub fn rebuild_exposure(storage: &mut dyn Storage) -> StdResult<()> {
+    let keys = ROUTE_EXPOSURE
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (route, denom) in keys {
+        ROUTE_EXPOSURE.remove(storage, (&route, &denom));
+    }
+    let exposed = INFLIGHT_PACKETS
+        .range(storage, None, None, Order::Ascending)
+        .filter(|item| !matches!(item, Ok((_, packet)) if packet.status == PacketLifecycleStatus::SendingExternalTokens))
+        .map(|item| item.map(|(_, packet)| packet))
+        .collect::<StdResult<Vec<_>>>()?;
+    for packet in exposed {
+        expose(storage, &packet, true)?;
+    }
+    Ok(())
+}